@@ -0,0 +1,38 @@
+//!
+//! Build script: record the versions of the ring buffer and port manager
+//! crates we're linked with (as Cargo.lock has them) so that
+//! ring2stdout --version can report them.  When there's no Cargo.lock to
+//! look at the version is given as unknown.
+//!
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let lock = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock.display());
+    let contents = fs::read_to_string(&lock).unwrap_or_default();
+
+    for (name, variable) in &[
+        ("nscldaq_ringbuffer", "RINGBUFFER_VERSION"),
+        ("portman_client", "PORTMAN_CLIENT_VERSION"),
+    ] {
+        let version = locked_version(&contents, name).unwrap_or("unknown");
+        println!("cargo:rustc-env={}={}", variable, version);
+    }
+}
+// The version of the package name in the Cargo.lock contents.  Each
+// package is a [[package]] table whose name line is followed by its
+// version line.
+//
+fn locked_version<'a>(lock: &'a str, name: &str) -> Option<&'a str> {
+    let name_line = format!("name = \"{}\"", name);
+    let mut lines = lock.lines();
+    lines.find(|line| line.trim() == name_line)?;
+    lines
+        .next()?
+        .trim()
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+}
@@ -0,0 +1,366 @@
+//!
+//! Soak test / benchmark of the drain loop.  We make a ring buffer
+//! file, feed it synthetic data from a producer thread at a chosen rate
+//! and drain it (as ring2stdout would) into a sink that throws the data
+//! away.  At the end the throughput and the latency from a record being
+//! put into the ring to its reaching the sink are reported, so the
+//! effect of the buffer size, timeout, queueing and flush policy
+//! settings can be measured rather than guessed at.
+//!
+//! No ringmaster is needed: the producer and consumer attach to the
+//! mapped ring directly.  Run it with e.g.
+//!
+//!    cargo run --release --example soak -- --seconds 10 --rate 200
+//!
+//! Each record the producer puts starts with the nanoseconds since we
+//! started, which is how the sink knows when it was put.
+//!
+
+use clap::{App, Arg, ArgMatches};
+use nscldaq_ringbuffer::ringbuffer::{consumer, producer, RingBufferMap};
+use nscldaq_ringtostdout::drain;
+use nscldaq_ringtostdout::ringmaster_client::ClientType;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const STAMP: usize = 8; // Bytes of timestamp at the start of each record.
+const CATCH_UP: Duration = Duration::from_millis(200); // Drain after producing.
+
+fn main() {
+    let args = App::new("soak")
+        .about("Drives the ring2stdout drain loop with synthetic data and reports how it did")
+        .arg(value(
+            "directory",
+            "directory",
+            "DIR",
+            "Where to make the ring (default: the temp directory)",
+        ))
+        .arg(value(
+            "ring_size",
+            "ring-size",
+            "BYTES",
+            "Ring data size (default 8388608)",
+        ))
+        .arg(value(
+            "seconds",
+            "seconds",
+            "SECONDS",
+            "How long to produce data (default 5)",
+        ))
+        .arg(value(
+            "rate",
+            "rate",
+            "MB/S",
+            "Production rate, 0 for as fast as possible (default 0)",
+        ))
+        .arg(value(
+            "record_size",
+            "record-size",
+            "BYTES",
+            "Size of each put (default 8192)",
+        ))
+        .arg(value(
+            "buffer_size",
+            "buffer-size",
+            "BYTES",
+            "Drain buffer size (default 1048576)",
+        ))
+        .arg(value(
+            "timeout_ms",
+            "timeout-ms",
+            "MS",
+            "Drain get timeout (default 1)",
+        ))
+        .arg(value(
+            "queue_depth",
+            "queue-depth",
+            "GETS",
+            "Write from a separate thread with this queue",
+        ))
+        .arg(value(
+            "flush_bytes",
+            "flush-bytes",
+            "BYTES",
+            "Flush after this many bytes (default: when idle)",
+        ))
+        .arg(
+            Arg::with_name("flush_each")
+                .long("flush-each")
+                .conflicts_with("flush_bytes")
+                .help("Flush after every get"),
+        )
+        .get_matches();
+
+    let directory = args
+        .value_of("directory")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let ring_size = number(&args, "ring_size", 8 * 1024 * 1024);
+    let seconds: f64 = number(&args, "seconds", 5.0);
+    let rate: f64 = number(&args, "rate", 0.0);
+    let record_size = std::cmp::max(number(&args, "record_size", 8192), STAMP);
+    if record_size > ring_size {
+        eprintln!("The record size can't be bigger than the ring");
+        process::exit(1);
+    }
+
+    let mut options = drain::Options::new();
+    options.buffer_size = number(&args, "buffer_size", options.buffer_size);
+    options.timeout = Duration::from_millis(number(&args, "timeout_ms", 1));
+    options.queue_depth = args
+        .value_of("queue_depth")
+        .map(|_| number(&args, "queue_depth", 1));
+    if args.is_present("flush_each") {
+        options.flush_policy = drain::FlushPolicy::Each;
+    } else if args.is_present("flush_bytes") {
+        options.flush_policy = drain::FlushPolicy::Bytes(number(&args, "flush_bytes", 0));
+    }
+
+    let ring_file = directory.join(format!("soak_{}", process::id()));
+    if let Err(e) = make_ring(&ring_file, ring_size) {
+        eprintln!("Unable to make the ring {}: {}", ring_file.display(), e);
+        process::exit(1);
+    }
+    let result = soak(&ring_file, seconds, rate, record_size, &options);
+    let _ = fs::remove_file(&ring_file);
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn value<'a>(name: &'a str, long: &'a str, value_name: &'a str, help: &'a str) -> Arg<'a, 'a> {
+    Arg::with_name(name)
+        .long(long)
+        .value_name(value_name)
+        .help(help)
+        .takes_value(true)
+}
+
+fn number<T: FromStr>(args: &ArgMatches, name: &str, default: T) -> T {
+    match args.value_of(name) {
+        None => default,
+        Some(text) => text.parse().unwrap_or_else(|_| {
+            eprintln!(
+                "--{} {} is not a valid number",
+                name.replace('_', "-"),
+                text
+            );
+            process::exit(1);
+        }),
+    }
+}
+
+// Produce for seconds at rate MB/s (flat out if 0) while draining the
+// ring into a LatencySink, then report:
+//
+fn soak(
+    ring_file: &Path,
+    seconds: f64,
+    rate: f64,
+    record_size: usize,
+    options: &drain::Options,
+) -> Result<(), String> {
+    let path = ring_file.to_str().ok_or("The ring path must be UTF-8")?;
+    let consumer_map = Arc::new(Mutex::new(RingBufferMap::new(path)?));
+    let consumer =
+        consumer::Consumer::attach(&consumer_map).map_err(|e| consumer::error_string(&e))?;
+    let producer_map = Arc::new(Mutex::new(RingBufferMap::new(path)?));
+    let producer =
+        producer::Producer::attach(&producer_map).map_err(|e| producer::error_string(&e))?;
+
+    let epoch = Instant::now();
+    let shutdown = AtomicBool::new(false);
+    let mut sink = LatencySink::new(epoch, record_size);
+    let (produced, (reason, stats)) = thread::scope(|scope| {
+        let production = scope.spawn(|| {
+            let produced = produce(producer, epoch, seconds, rate, record_size);
+            thread::sleep(CATCH_UP);
+            shutdown.store(true, Ordering::SeqCst);
+            produced
+        });
+        let mut rings = [ClientType::Consumer(consumer)];
+        let result = match options.queue_depth {
+            Some(_) => drain::drain_queued(&mut rings, &mut sink, options, &shutdown),
+            None => drain::drain(&mut rings, &mut sink, options, &shutdown),
+        };
+        (production.join().unwrap(), result)
+    });
+
+    let elapsed = stats.elapsed().as_secs_f64();
+    println!("Stopped: {:?}", reason);
+    println!(
+        "Produced {} bytes, drained {} bytes in {} gets ({} timed out) over {:.3}s",
+        produced, stats.bytes, stats.gets, stats.timeouts, elapsed
+    );
+    if produced > stats.bytes {
+        // Left in the ring at the end, or overwritten before we got to it:
+
+        println!("{} bytes were not drained", produced - stats.bytes);
+    }
+    println!(
+        "Throughput: {:.1} MB/s (including the {}ms at the end without data)",
+        stats.bytes as f64 / elapsed / 1.0e6,
+        CATCH_UP.as_millis()
+    );
+    sink.report();
+    Ok(())
+}
+
+// Put records into the ring, pacing them to rate MB/s (not at all if
+// it's 0), for seconds.  Returns the bytes put.
+//
+fn produce(
+    mut producer: producer::Producer,
+    epoch: Instant,
+    seconds: f64,
+    rate: f64,
+    record_size: usize,
+) -> u64 {
+    let mut record = vec![0_u8; record_size];
+    for (i, byte) in record[STAMP..].iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let end = Duration::from_secs_f64(seconds);
+    let mut produced: u64 = 0;
+    loop {
+        let now = epoch.elapsed();
+        if now >= end {
+            break;
+        }
+        if rate > 0.0 {
+            let due = Duration::from_secs_f64(produced as f64 / (rate * 1.0e6));
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+        let stamp = epoch.elapsed().as_nanos() as u64;
+        record[..STAMP].copy_from_slice(&stamp.to_ne_bytes());
+        if producer
+            .timed_put(&record, Duration::from_millis(100))
+            .is_ok()
+        {
+            produced += record_size as u64;
+        }
+    }
+    produced
+}
+
+// A sink that discards what's written to it after noting, for each
+// record, how long it took from being put to arriving here.  Records
+// can be split across writes so the timestamp is collected a byte at a
+// time if need be.
+//
+struct LatencySink {
+    epoch: Instant,
+    record_size: usize,
+    offset: usize, // Where we are in the current record.
+    stamp: [u8; STAMP],
+    latencies: Vec<u64>, // Microseconds.
+}
+
+impl LatencySink {
+    fn new(epoch: Instant, record_size: usize) -> LatencySink {
+        LatencySink {
+            epoch,
+            record_size,
+            offset: 0,
+            stamp: [0; STAMP],
+            latencies: Vec::new(),
+        }
+    }
+    fn report(&mut self) {
+        if self.latencies.is_empty() {
+            println!("No records arrived");
+            return;
+        }
+        self.latencies.sort_unstable();
+        let percentile = |p: f64| {
+            let i = ((self.latencies.len() - 1) as f64 * p / 100.0).round() as usize;
+            self.latencies[i]
+        };
+        println!(
+            "Latency (us) over {} records: p50 {} p90 {} p99 {} p99.9 {} max {}",
+            self.latencies.len(),
+            percentile(50.0),
+            percentile(90.0),
+            percentile(99.0),
+            percentile(99.9),
+            self.latencies[self.latencies.len() - 1]
+        );
+    }
+}
+
+impl Write for LatencySink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let arrived = self.epoch.elapsed().as_nanos() as u64;
+        let mut rest = data;
+        while !rest.is_empty() {
+            if self.offset < STAMP {
+                let n = std::cmp::min(STAMP - self.offset, rest.len());
+                self.stamp[self.offset..self.offset + n].copy_from_slice(&rest[..n]);
+                self.offset += n;
+                rest = &rest[n..];
+                if self.offset == STAMP {
+                    let put = u64::from_ne_bytes(self.stamp);
+                    self.latencies.push(arrived.saturating_sub(put) / 1000);
+                }
+            } else {
+                let n = std::cmp::min(self.record_size - self.offset, rest.len());
+                self.offset += n;
+                rest = &rest[n..];
+            }
+            if self.offset == self.record_size {
+                self.offset = 0;
+            }
+        }
+        Ok(data.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Make a ring buffer file with data_bytes of data area and 4 consumer
+// slots, laid out as nscldaq_ringbuffer expects, with everything free.
+//
+fn make_ring(path: &Path, data_bytes: usize) -> io::Result<()> {
+    let max_consumers: usize = 4;
+    let word = std::mem::size_of::<usize>();
+    let producer_offset = 32 + 6 * word;
+    let slot_size = 2 * word; // offset and pid padded to alignment.
+    let consumer_offset = producer_offset + slot_size;
+    let data_offset = consumer_offset + max_consumers * slot_size;
+    let top_offset = data_offset + data_bytes - 1;
+
+    let mut contents = Vec::<u8>::with_capacity(top_offset + 1);
+    let mut magic = [0_u8; 32];
+    magic[0..8].copy_from_slice(b"NSCLRing");
+    contents.extend_from_slice(&magic);
+    for field in &[
+        max_consumers,
+        data_bytes,
+        producer_offset,
+        consumer_offset,
+        data_offset,
+        top_offset,
+    ] {
+        contents.extend_from_slice(&field.to_ne_bytes());
+    }
+    for _ in 0..max_consumers + 1 {
+        let mut slot = vec![0_u8; slot_size];
+        slot[0..word].copy_from_slice(&data_offset.to_ne_bytes());
+        slot[word..word + 4].copy_from_slice(&0xffffffff_u32.to_ne_bytes());
+        contents.extend_from_slice(&slot);
+    }
+    contents.resize(top_offset + 1, 0);
+    fs::write(path, contents)
+}
@@ -0,0 +1,131 @@
+//!
+//! The drain::asynchronous module (only with the async feature) lets
+//! programs built on tokio forward a ring to an AsyncWrite.  Getting
+//! data from a ring blocks (it waits on shared memory) so the drain loop
+//! runs on tokio's blocking thread pool and hands what it would have
+//! written, a get at a time, to the async side which writes it to the
+//! sink.  The caller just awaits run_to_writer_async.
+//!
+
+use super::forward::{drain, write_failure, Options, RunError, Stats, StopReason};
+use crate::ringmaster_client;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+// What the drain loop writes to.  As for drain_queued, writes are
+// collected and each flush (drain flushes after every get when there's
+// a queue_depth) sends what was collected as one message.
+
+struct ChannelWriter {
+    pending: Vec<u8>,
+    channel: mpsc::Sender<Vec<u8>>,
+}
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let message = std::mem::take(&mut self.pending);
+        self.channel
+            .blocking_send(message)
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+// Stops the drain loop when dropped: when we're done with it or if our
+// future is dropped before it finishes.  An idle drain loop never
+// writes, so it wouldn't notice the channel closing.
+
+struct StopOnDrop(Arc<AtomicBool>);
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+///
+/// The async version of run_to_writer: attach to the local ring in
+/// ring_path as a consumer and forward its data to out until the ring
+/// ends or options.max_bytes have been forwarded.  The ring is read on
+/// tokio's blocking thread pool (see tokio::task::spawn_blocking) so
+/// this must be awaited within a tokio runtime.  Up to
+/// options.queue_depth gets (1 if None) wait to be written to out; when
+/// out falls that far behind the ring reading waits for it.
+/// On success, the statistics of the run are returned.  out being closed
+/// by its reader counts as success.
+///
+pub async fn run_to_writer_async<W: AsyncWrite + Unpin>(
+    ring_path: impl AsRef<Path>,
+    options: Options,
+    mut out: W,
+) -> Result<Stats, RunError> {
+    let mut options = options;
+    let depth = std::cmp::max(*options.queue_depth.get_or_insert(1), 1);
+    let (sender, receiver) = mpsc::channel::<Vec<u8>>(depth);
+    let stop = StopOnDrop(Arc::new(AtomicBool::new(false)));
+    let shutdown = Arc::clone(&stop.0);
+    let ring_path = PathBuf::from(ring_path.as_ref());
+
+    let forwarder = tokio::task::spawn_blocking(move || {
+        let mut client =
+            vec![ringmaster_client::attach_consumer(&ring_path).map_err(RunError::Ring)?];
+        let writer = ChannelWriter {
+            pending: Vec::new(),
+            channel: sender,
+        };
+        Ok::<_, RunError>(drain(&mut client, writer, &options, &shutdown))
+    });
+    let written = write_messages(receiver, &mut out).await;
+    drop(stop);
+
+    // The ring reading task panicking is passed on; it can also be
+    // cancelled, if the runtime is shutting down, which we report as the
+    // output being interrupted:
+
+    let (reason, stats) = match forwarder.await {
+        Ok(result) => result?,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => {
+            return Err(RunError::Output(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "The ring reading task was cancelled",
+            )))
+        }
+    };
+    let reason = match (reason, written) {
+        (StopReason::RingError(e), _) => return Err(RunError::Ring(e)),
+        (_, Err(e)) => write_failure(e),
+        (reason, Ok(())) => reason,
+    };
+    match reason {
+        StopReason::OutputError(e) => Err(RunError::Output(e)),
+        StopReason::NoData => Err(RunError::NoData),
+        _ => Ok(stats),
+    }
+}
+// Write the messages from the drain loop to out until it's done (and so
+// has closed the channel), flushing out whenever we've caught up.  On
+// returning, receiver is dropped which tells the drain loop to stop if
+// it's still going.
+//
+async fn write_messages<W: AsyncWrite + Unpin>(
+    mut receiver: mpsc::Receiver<Vec<u8>>,
+    out: &mut W,
+) -> io::Result<()> {
+    while let Some(message) = receiver.recv().await {
+        out.write_all(&message).await?;
+        if receiver.is_empty() {
+            out.flush().await?;
+        }
+    }
+    out.flush().await
+}
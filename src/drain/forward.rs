@@ -0,0 +1,890 @@
+//!
+//! The drain::forward module contains the loop that takes the data
+//! from one or more ring buffer consumers and forwards it to
+//! a writer.  This is what the ring2stdout program is built on, but
+//! other programs can use run_to_writer to drain a ring into
+//! anything that implements Write without spawning a ring2stdout.
+//!
+
+use super::histogram::GetHistogram;
+use super::items::{ItemAligner, ItemCounter};
+use super::progress::Progress;
+use crate::framing;
+use crate::ringmaster_client;
+use crate::ringmaster_client::RingReader;
+use log::{debug, info, log_enabled, warn, Level};
+use nscldaq_ringbuffer::ringbuffer::consumer;
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+///
+/// Options that control how data is forwarded by drain (and
+/// drain_queued).  Options::new gives the ring2stdout defaults.
+///
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Size of the chunks read from the rings.
+    pub buffer_size: usize,
+    /// How long each get waits for data.  Zero polls.
+    pub timeout: Duration,
+    /// Read for throughput rather than latency: each get waits up to
+    /// BULK_TIMEOUT (instead of timeout) for a whole buffer_size of data.
+    /// While the ring keeps up every get is full, but data can sit in the
+    /// ring that long before it's forwarded and that's also how long it
+    /// can take to notice shutdown.
+    pub bulk: bool,
+    /// If Some, stop after forwarding this many data bytes.  Gets are
+    /// trimmed so no more than that is read.  With item_align only the
+    /// whole items among them are written.
+    pub max_bytes: Option<u64>,
+    /// If Some, stop after forwarding this many complete NSCLDAQ ring
+    /// items, counted over all the rings.  The rest of the get holding the
+    /// end of the last item is discarded.
+    pub max_items: Option<u64>,
+    /// Only write whole NSCLDAQ ring items from each ring: the end of a get
+    /// that's part of an item is held back until the rest has been read,
+    /// so a frame never splits an item.  Items bigger than buffer_size are
+    /// warned about and written unaligned as they come, so no more than a
+    /// get's worth is held.  What's held when we stop, or when a ring is
+    /// reattached, is discarded.
+    pub item_align: bool,
+    /// If Some, log statistics this often.  The clock is looked at before
+    /// every get so reports keep coming while the rings are idle.
+    pub stats_interval: Option<Duration>,
+    /// Write the statistics to stderr as single line JSON objects rather
+    /// than logging them.
+    pub json_stats: bool,
+    /// Frame the data (see framing) even if there's only one ring.
+    /// Several rings are always framed.
+    pub frame: bool,
+    /// If Some, a ring whose gets have timed out for this long and that
+    /// has no producer (or is remote, where we can't tell) has ended.
+    pub exit_on_idle: Option<Duration>,
+    /// If Some, when no data has arrived from any ring for this long, log
+    /// a heartbeat line, and again each time this long passes without data.
+    pub heartbeat: Option<Duration>,
+    /// If Some, a write (or flush) of the output that blocks for at least
+    /// this long logs a warning that downstream is slow.
+    pub slow_threshold: Option<Duration>,
+    /// With slow_threshold, after a slow write the gets of the next
+    /// slow_threshold are dropped (whole, so framing stays intact) and
+    /// counted in the stats.  The rings stay drained so producers don't
+    /// stall, but this loses data!
+    pub drop_on_slow: bool,
+    /// If Some, the most bytes per second written to the output.  Gets
+    /// are limited to a tenth of a second's worth and we sleep after one
+    /// that puts us over the rate, so no pause is much over 0.1 seconds.
+    pub rate_limit: Option<u64>,
+    /// How many retryable ring errors in a row (see
+    /// ringmaster_client::Error::is_retryable) are logged and ignored
+    /// before we give up.  Only data ends a row; timeouts neither count
+    /// nor end it.
+    pub retry_limit: u32,
+    /// Discard what each ring gives us until its first timeout (when
+    /// we've caught up with its producer); only data after that is
+    /// forwarded.
+    pub skip_backlog: bool,
+    /// When a ring goes away attach to it again rather than stopping.  A
+    /// local ring has gone if, when a get times out, its file has been
+    /// deleted or replaced (looked at once a second at most); a remote
+    /// ring when its connection closes or fails.  Data either side of a
+    /// reattach isn't contiguous; reattaches are logged and counted.
+    pub reattach: bool,
+    /// Keep a CRC32 and byte count of everything written to the output
+    /// (see Checksum) in the stats.
+    pub checksum: bool,
+    /// If Some, once idle_after gets in a row (from any ring) have timed
+    /// out we sleep between gets, starting at a millisecond and doubling
+    /// up to this long, until data arrives again.  This saves CPU when the
+    /// rings are idle.
+    pub idle_sleep: Option<Duration>,
+    /// The number of timeouts in a row before idle_sleep starts.
+    pub idle_after: u32,
+    /// When the output is flushed (see FlushPolicy).
+    pub flush_policy: FlushPolicy,
+    /// The most gets drain_queued holds waiting for the output.  If Some,
+    /// drain flushes the output after each get.
+    pub queue_depth: Option<usize>,
+    /// How many buffers drain_queued allocates and recycles between
+    /// reading and writing.  None means two more than queue_depth, 0 no
+    /// recycling.
+    pub buffer_pool: Option<usize>,
+    /// When a get times out, check (once a second at most) that the ring's
+    /// ringmaster connection is alive (see RingReader::connected).  A dead
+    /// one is a ring error or, with reattach, is reattached.
+    pub check_connections: bool,
+    /// If Some, stop (StopReason::NoData) if no get has returned data this
+    /// long after we start.  Once data has arrived it no longer applies.
+    pub first_data_timeout: Option<Duration>,
+    /// Redraw a line on stderr a few times a second showing how far we are
+    /// through max_bytes (without one, the bytes so far by a spinner).
+    /// Only for when stderr is a terminal.
+    pub progress: bool,
+    /// Keep a GetHistogram of the gets (in the stats).
+    pub latency_histogram: bool,
+    /// Don't log the gets that time out.  Otherwise they are logged at the
+    /// debug level (which, polling an idle ring, is a lot of lines).
+    pub quiet_timeouts: bool,
+    /// If Some, included in the json_stats lines so those from several
+    /// instances can be told apart.  It's written as is so it must not
+    /// need escaping in a JSON string.
+    pub name: Option<String>,
+}
+impl Options {
+    /// Options with the ring2stdout defaults: 1MByte reads with a
+    /// 1ms timeout, no limit, statistics or framing, up to 10
+    /// retryable errors in a row and no idle sleep (but if it's turned on
+    /// it starts after 100 timeouts).
+    ///
+    pub fn new() -> Options {
+        Options {
+            buffer_size: 1024 * 1024,
+            timeout: Duration::from_millis(1),
+            bulk: false,
+            max_bytes: None,
+            max_items: None,
+            item_align: false,
+            stats_interval: None,
+            json_stats: false,
+            frame: false,
+            exit_on_idle: None,
+            heartbeat: None,
+            slow_threshold: None,
+            drop_on_slow: false,
+            rate_limit: None,
+            retry_limit: 10,
+            skip_backlog: false,
+            reattach: false,
+            checksum: false,
+            idle_sleep: None,
+            idle_after: 100,
+            flush_policy: FlushPolicy::Idle,
+            queue_depth: None,
+            buffer_pool: None,
+            check_connections: false,
+            first_data_timeout: None,
+            progress: false,
+            latency_histogram: false,
+            quiet_timeouts: false,
+            name: None,
+        }
+    }
+}
+impl Default for Options {
+    fn default() -> Options {
+        Options::new()
+    }
+}
+
+///
+/// When drain flushes its output buffer.  Whatever the policy, the
+/// output is flushed when a get times out (the rings have gone quiet) and
+/// when we stop.
+///
+/// *  Each - after every get; the lowest latency but the most (and
+///    smallest) writes.
+/// *  Idle - only when a get times out; the best throughput, but while
+///    data keeps coming it's written in buffer_size writes.  The default.
+/// *  Bytes(n) - whenever at least n bytes have been written since the
+///    last flush; bounds how much data is held back.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlushPolicy {
+    Each,
+    Idle,
+    Bytes(u64),
+}
+
+///
+/// Reasons drain stops forwarding data.  OutputClosed means whoever
+/// reads our output went away (broken pipe or, for sockets, connection
+/// reset); for a forwarding process
+/// that's a normal way to end, not a failure.  Any other failure to write
+/// the output is an OutputError.  NoData means no data arrived within
+/// Options::first_data_timeout.
+///
+#[derive(Debug)]
+pub enum StopReason {
+    EndOfData,
+    LimitReached,
+    Shutdown,
+    OutputClosed,
+    NoData,
+    RingError(ringmaster_client::Error),
+    OutputError(io::Error),
+}
+
+///
+/// Errors run_to_writer can fail with:
+///
+#[derive(Debug)]
+pub enum RunError {
+    Ring(ringmaster_client::Error),
+    Output(io::Error),
+    NoData,
+}
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Ring(e) => write!(f, "Ring buffer error: {}", e),
+            RunError::Output(e) => write!(f, "Failed writing output: {}", e),
+            RunError::NoData => write!(f, "No data arrived in time"),
+        }
+    }
+}
+
+///
+/// A running CRC32 and count of the bytes written to the output.
+/// This covers the output stream exactly as written (including any
+/// frame headers) so it can be compared with e.g. the crc32 of an
+/// output file when chasing data loss.
+///
+#[derive(Debug, Clone)]
+pub struct Checksum {
+    pub bytes: u64,
+    hasher: crc32fast::Hasher,
+}
+impl Checksum {
+    pub(super) fn new() -> Checksum {
+        Checksum {
+            bytes: 0,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+    pub(super) fn update(&mut self, data: &[u8]) {
+        self.bytes += data.len() as u64;
+        self.hasher.update(data);
+    }
+    /// The CRC32 of the bytes written so far.
+    ///
+    pub fn crc32(&self) -> u32 {
+        self.hasher.clone().finalize()
+    }
+}
+
+///
+/// Running statistics about the data we've forwarded.  These
+/// feed the periodic stats reports and are returned when we stop.
+/// checksum is only kept if Options::checksum was set.
+/// bytes and gets count all data read from the rings, the dropped_
+/// fields count what of that was dropped by Options::drop_on_slow.
+/// skipped_bytes is the backlog discarded by Options::skip_backlog, it's
+/// not included in bytes.  items counts the ring items read if
+/// Options::max_items was set (otherwise we don't look for items).
+/// reattaches counts Options::reattach reattaches and timeouts the gets
+/// that timed out (found no data).  buffer_allocations is how many
+/// buffers drain_queued allocated for its queue.  histogram is only kept if
+/// Options::latency_histogram was set.
+///
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub bytes: u64,
+    pub gets: u64,
+    pub items: u64,
+    pub dropped_gets: u64,
+    pub dropped_bytes: u64,
+    pub skipped_bytes: u64,
+    pub reattaches: u64,
+    pub timeouts: u64,
+    pub buffer_allocations: u64,
+    pub checksum: Option<Checksum>,
+    pub histogram: Option<GetHistogram>,
+    started: Instant,
+    last_report: Instant,
+    last_report_bytes: u64,
+}
+impl Stats {
+    fn new(options: &Options) -> Stats {
+        Stats {
+            bytes: 0,
+            gets: 0,
+            items: 0,
+            dropped_gets: 0,
+            dropped_bytes: 0,
+            skipped_bytes: 0,
+            reattaches: 0,
+            timeouts: 0,
+            buffer_allocations: 0,
+            checksum: if options.checksum {
+                Some(Checksum::new())
+            } else {
+                None
+            },
+            histogram: if options.latency_histogram {
+                Some(GetHistogram::new())
+            } else {
+                None
+            },
+            started: Instant::now(),
+            last_report: Instant::now(),
+            last_report_bytes: 0,
+        }
+    }
+    ///
+    /// How long it's been since we started forwarding.
+    ///
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+    // Count a successful get of n bytes:
+    //
+    fn record_get(&mut self, n: usize) {
+        self.bytes += n as u64;
+        self.gets += 1;
+        if let Some(histogram) = &mut self.histogram {
+            histogram.record(n, Instant::now());
+        }
+    }
+    // Count a get of n bytes that was dropped rather than written:
+    //
+    fn record_drop(&mut self, n: usize) {
+        self.dropped_bytes += n as u64;
+        self.dropped_gets += 1;
+    }
+    // Account for bytes written to the output in the checksum if we're
+    // keeping one:
+    //
+    fn record_output(&mut self, data: &[u8]) {
+        if let Some(checksum) = &mut self.checksum {
+            checksum.update(data);
+        }
+    }
+    // If at least interval has passed since the last report, write
+    // a stats line to the log, or as JSON to stderr if json is true.
+    // The totals are since we started, the rate is over the time since
+    // the last report.  A name is added to the JSON.
+    //
+    fn report_if_due(&mut self, interval: Duration, json: bool, name: Option<&str>) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_report);
+        if elapsed >= interval {
+            let rate = (self.bytes - self.last_report_bytes) as f64 / elapsed.as_secs_f64() / 1.0e6;
+            if json {
+                let name = name.map(|n| format!("\"name\":\"{}\",", n));
+                eprintln!(
+                    "{{{}\"bytes\":{},\"gets\":{},\"timeouts\":{},\"rate_mbps\":{:.3},\"uptime_s\":{:.3}}}",
+                    name.unwrap_or_default(),
+                    self.bytes,
+                    self.gets,
+                    self.timeouts,
+                    rate,
+                    now.duration_since(self.started).as_secs_f64()
+                );
+            } else {
+                info!(
+                    "stats: {} bytes, {:.1} MB/s, {} gets, {} timeouts",
+                    self.bytes, rate, self.gets, self.timeouts
+                );
+            }
+            self.last_report = now;
+            self.last_report_bytes = self.bytes;
+        }
+    }
+}
+
+///
+/// How long each get waits for a full chunk with Options::bulk.  It bounds
+/// how long a slow trickle of data is held and how long a shutdown waits.
+///
+pub const BULK_TIMEOUT: Duration = Duration::from_millis(250);
+
+// The first, shortest, sleep once the rings are idle (see Options::idle_sleep):
+
+const IDLE_SLEEP_START: Duration = Duration::from_millis(1);
+
+// A token bucket that paces writes to a rate in bytes/sec.  The bucket
+// holds at most burst bytes (a tenth of a second's worth) so we can
+// get ahead of the rate only briefly, and gets are limited to burst
+// bytes so no one pause is longer than about 0.1 seconds.
+//
+struct RateLimiter {
+    rate: f64,
+    burst: usize,
+    tokens: f64,
+    last: Instant,
+}
+impl RateLimiter {
+    fn new(rate: u64) -> RateLimiter {
+        let burst = std::cmp::max(rate / 10, 1) as usize;
+        RateLimiter {
+            rate: rate as f64,
+            burst,
+            tokens: burst as f64,
+            last: Instant::now(),
+        }
+    }
+    // Account for n bytes written, sleeping until the rate allows them
+    // if we're over budget:
+    //
+    fn pace(&mut self, n: usize) {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.burst as f64) - n as f64;
+        self.last = now;
+        if self.tokens < 0.0 {
+            thread::sleep(Duration::from_secs_f64(-self.tokens / self.rate));
+            self.tokens = 0.0;
+            self.last = Instant::now();
+        }
+    }
+}
+
+///
+/// Attach to the local ring in ring_path as a consumer and forward its
+/// data to out until the ring ends or options.max_bytes have been
+/// forwarded.  On success, the statistics of the run are returned.
+/// out being closed by its reader counts as success.
+///
+pub fn run_to_writer(
+    ring_path: impl AsRef<Path>,
+    options: Options,
+    out: impl Write,
+) -> Result<Stats, RunError> {
+    let mut client = vec![ringmaster_client::attach_consumer(ring_path).map_err(RunError::Ring)?];
+    let never = AtomicBool::new(false);
+    match drain(&mut client, out, &options, &never) {
+        (StopReason::RingError(e), _) => Err(RunError::Ring(e)),
+        (StopReason::OutputError(e), _) => Err(RunError::Output(e)),
+        (StopReason::NoData, _) => Err(RunError::NoData),
+        (_, stats) => Ok(stats),
+    }
+}
+
+///
+/// Forward data from the rings to out.
+/// The rings can be anything that's a RingReader, usually
+/// ringmaster_client::RingClient objects.  To mix kinds of reader
+/// use Box<dyn RingReader>.
+/// Each get, we try to do in options.buffer_size chunks.  We use timed_get
+/// with options.timeout to reduce latency.
+/// With several rings, they are polled round-robin and each chunk is
+/// framed (see framing) so the rings can be separated downstream.
+/// Output goes through a buffered writer.  It's flushed whenever a get
+/// times out (so data is not held while the rings are idle), when we
+/// stop and as options.flush_policy says.
+/// We stop when all the rings have ended, a limit in options is reached,
+/// shutdown is set (e.g. by a signal handler; the data in hand is written
+/// first), writing the output fails (see StopReason::OutputClosed) or a
+/// ring has an error we don't retry.  The other options are described
+/// with Options.  To write the output from a separate thread use
+/// drain_queued.
+///
+/// The reason we stopped and the statistics are returned.
+///
+pub fn drain<R: RingReader, W: Write>(
+    rings: &mut [R],
+    out: W,
+    options: &Options,
+    shutdown: &AtomicBool,
+) -> (StopReason, Stats) {
+    // We must use a vec -- or a static buffer else the buffer will
+    // overflow the stack.  Vec will allocate on the heap,
+    // Note that evidently, the vector & can be treated as &[u8] which is
+    // what both timed_get and write_all need.
+    // The buffer is allocated once and reused for every get.  vec! of
+    // zeros gets its memory already zeroed from the allocator (fresh pages
+    // for a buffer this size) so there's no memset to avoid, and handing
+    // timed_get uninitialized memory as a &mut [u8] would be undefined
+    // behavior.  Only the n bytes a get returns are ever written out.
+    let mut data = vec![0_u8; options.buffer_size];
+    let mut stats = Stats::new(options);
+    let mut out = io::BufWriter::with_capacity(options.buffer_size, out);
+    let framed = options.frame || rings.len() > 1;
+    let mut ended = vec![false; rings.len()]; // Rings can end.
+    let mut last_data = vec![Instant::now(); rings.len()];
+    let mut skipping = vec![options.skip_backlog; rings.len()];
+    let mut item_counters = vec![ItemCounter::new(); rings.len()];
+    let mut aligners = vec![ItemAligner::new(); rings.len()];
+    let mut ring_index = 0;
+    let mut drop_until: Option<Instant> = None; // drop_on_slow window.
+    let mut retries = 0; // Retryable errors since the last data.
+    let mut limiter = options.rate_limit.map(RateLimiter::new);
+    let mut idle_since = Instant::now(); // Only kept with a heartbeat.
+    let mut last_beat = idle_since;
+    let mut last_replace_check = vec![Instant::now(); rings.len()];
+    let mut last_connection_check = vec![Instant::now(); rings.len()];
+    let mut timeouts = 0; // In a row, only counted with an idle sleep.
+    let log_timeouts = !options.quiet_timeouts && log_enabled!(Level::Debug);
+    let mut idle_delay = IDLE_SLEEP_START;
+    let mut unflushed: u64 = 0; // Bytes written since the last flush.
+    let mut progress = if options.progress {
+        Some(Progress::new(options.max_bytes))
+    } else {
+        None
+    };
+    let mut had_data = false; // Only kept with a first_data_timeout.
+    let timeout = if options.bulk {
+        BULK_TIMEOUT
+    } else {
+        options.timeout
+    };
+    let reason = loop {
+        if shutdown.load(Ordering::Relaxed) {
+            break StopReason::Shutdown;
+        }
+        if let Some(limit) = options.first_data_timeout {
+            if !had_data && stats.elapsed() >= limit {
+                break StopReason::NoData;
+            }
+        }
+        if let Some(progress) = &mut progress {
+            progress.update(stats.bytes);
+        }
+        if let Some(interval) = options.stats_interval {
+            stats.report_if_due(interval, options.json_stats, options.name.as_deref());
+        }
+        let mut want = data.len();
+        if let Some(limit) = options.max_bytes {
+            let remaining = limit - stats.bytes;
+            if remaining == 0 {
+                break StopReason::LimitReached;
+            }
+            if remaining < want as u64 {
+                want = remaining as usize;
+            }
+        }
+        if options.max_items.is_some_and(|limit| stats.items >= limit) {
+            break StopReason::LimitReached;
+        }
+        if let Some(limiter) = &limiter {
+            want = std::cmp::min(want, limiter.burst);
+        }
+        // Next ring in the rotation that still has data coming:
+
+        if ended.iter().all(|e| *e) {
+            break StopReason::EndOfData;
+        }
+        while ended[ring_index] {
+            ring_index = (ring_index + 1) % rings.len();
+        }
+        let this_ring = ring_index;
+        ring_index = (ring_index + 1) % rings.len();
+
+        match rings[this_ring].timed_get(&mut data[0..want], timeout) {
+            Ok(0) => {
+                // The data source went away (remote ringmaster closed):
+
+                if options.reattach {
+                    if let Some(reason) = reattach_ring(
+                        &mut rings[this_ring],
+                        this_ring,
+                        &mut out,
+                        &mut stats,
+                        shutdown,
+                    ) {
+                        break reason;
+                    }
+                    aligners[this_ring].clear();
+                } else {
+                    ended[this_ring] = true;
+                }
+            }
+            Ok(n) if skipping[this_ring] => {
+                retries = 0;
+                timeouts = 0;
+                had_data = true;
+                idle_delay = IDLE_SLEEP_START;
+                stats.skipped_bytes += n as u64;
+                if options.exit_on_idle.is_some() {
+                    last_data[this_ring] = Instant::now();
+                }
+            }
+            Ok(n) => {
+                retries = 0;
+                timeouts = 0;
+                had_data = true;
+                idle_delay = IDLE_SLEEP_START;
+
+                // With an item limit only write up to the end of the
+                // last item we want:
+
+                let mut n = n;
+                if let Some(limit) = options.max_items {
+                    let (end, items) =
+                        item_counters[this_ring].scan(&data[0..n], limit - stats.items);
+                    stats.items += items;
+                    n = end;
+                }
+
+                // Actually read n bytes.  We need to send them as binary
+                // to the output unless we're dropping data for a slow
+                // downstream.  When aligning items, what we send is the
+                // whole items we now have, which may be none.
+
+                stats.record_get(n);
+                let chunk = if options.item_align {
+                    aligners[this_ring].align(&data[0..n], options.buffer_size)
+                } else {
+                    &data[0..n]
+                };
+                let n = chunk.len();
+                if n == 0 {
+                    // Nothing whole to write yet.
+                } else if drop_until.is_some_and(|t| Instant::now() < t) {
+                    stats.record_drop(n);
+                } else {
+                    let write_start = options.slow_threshold.map(|_| Instant::now());
+                    if framed {
+                        let header = framing::encode_header(this_ring as u32, n as u32);
+                        if let Err(e) = out.write_all(&header) {
+                            break write_failure(e);
+                        }
+                        stats.record_output(&header);
+                    }
+                    if let Err(e) = out.write_all(chunk) {
+                        break write_failure(e);
+                    }
+                    stats.record_output(chunk);
+                    unflushed += n as u64;
+                    if framed {
+                        unflushed += framing::FRAME_HEADER_SIZE as u64;
+                    }
+
+                    // With a rate limit we mustn't let the buffer save up
+                    // data to write in bursts; when queuing each get is
+                    // its own message:
+
+                    let flush = match options.flush_policy {
+                        FlushPolicy::Each => true,
+                        FlushPolicy::Idle => false,
+                        FlushPolicy::Bytes(limit) => unflushed >= limit,
+                    };
+                    if flush || limiter.is_some() || options.queue_depth.is_some() {
+                        if let Err(e) = out.flush() {
+                            break write_failure(e);
+                        }
+                        unflushed = 0;
+                    }
+                    if let Some(limiter) = &mut limiter {
+                        limiter.pace(n);
+                    }
+                    drop_until = check_slow(write_start, options, &stats);
+                }
+                if options.exit_on_idle.is_some() {
+                    last_data[this_ring] = Instant::now();
+                }
+                if options.heartbeat.is_some() {
+                    idle_since = Instant::now();
+                    last_beat = idle_since;
+                }
+            }
+            Err(e) => {
+                // Time out is ok, other retryable errors are ok up to
+                // options.retry_limit in a row and anything else is fatal:
+
+                match e {
+                    ringmaster_client::Error::ConsumerError(consumer::Error::Timeout) => {
+                        stats.timeouts += 1;
+                        if log_timeouts {
+                            debug!("Get from ring {} timed out", this_ring);
+                        }
+                        if skipping[this_ring] {
+                            skipping[this_ring] = false;
+                            info!(
+                                "Skipped {} bytes of backlog so far; forwarding ring {}",
+                                stats.skipped_bytes, this_ring
+                            );
+                        }
+                        let flush_start = options.slow_threshold.map(|_| Instant::now());
+                        if let Err(e) = out.flush() {
+                            break write_failure(e);
+                        }
+                        unflushed = 0;
+                        if drop_until.is_none() {
+                            drop_until = check_slow(flush_start, options, &stats);
+                        }
+                        if let Some(interval) = options.heartbeat {
+                            if last_beat.elapsed() >= interval {
+                                info!(
+                                    "heartbeat: idle {}s, {} bytes total",
+                                    idle_since.elapsed().as_secs(),
+                                    stats.bytes
+                                );
+                                last_beat = Instant::now();
+                            }
+                        }
+                        if options.reattach
+                            && last_replace_check[this_ring].elapsed() >= Duration::from_secs(1)
+                        {
+                            last_replace_check[this_ring] = Instant::now();
+                            if rings[this_ring].replaced() {
+                                if let Some(reason) = reattach_ring(
+                                    &mut rings[this_ring],
+                                    this_ring,
+                                    &mut out,
+                                    &mut stats,
+                                    shutdown,
+                                ) {
+                                    break reason;
+                                }
+                                aligners[this_ring].clear();
+                                last_data[this_ring] = Instant::now();
+                                continue;
+                            }
+                        }
+                        if options.check_connections
+                            && last_connection_check[this_ring].elapsed() >= Duration::from_secs(1)
+                        {
+                            last_connection_check[this_ring] = Instant::now();
+                            if let Err(e) = rings[this_ring].connected() {
+                                if !options.reattach {
+                                    break StopReason::RingError(e);
+                                }
+                                warn!("Lost ring {}'s ringmaster connection: {}", this_ring, e);
+                                if let Some(reason) = reattach_ring(
+                                    &mut rings[this_ring],
+                                    this_ring,
+                                    &mut out,
+                                    &mut stats,
+                                    shutdown,
+                                ) {
+                                    break reason;
+                                }
+                                aligners[this_ring].clear();
+                                last_data[this_ring] = Instant::now();
+                                continue;
+                            }
+                        }
+                        if let Some(idle_limit) = options.exit_on_idle {
+                            if last_data[this_ring].elapsed() >= idle_limit
+                                && rings[this_ring].producer_attached() != Some(true)
+                            {
+                                ended[this_ring] = true;
+                            }
+                        }
+                        if let Some(longest) = options.idle_sleep {
+                            if timeouts >= options.idle_after {
+                                thread::sleep(std::cmp::min(idle_delay, longest));
+                                idle_delay = std::cmp::min(idle_delay * 2, longest);
+                            } else {
+                                timeouts += 1;
+                            }
+                        }
+                    }
+                    _ if e.is_retryable() && retries < options.retry_limit => {
+                        retries += 1;
+                        warn!(
+                            "Retrying after error reading ring {} ({} of {}): {}",
+                            this_ring, retries, options.retry_limit, e
+                        );
+                    }
+                    ringmaster_client::Error::RingMasterIo(_) if options.reattach => {
+                        // Broken remote connection:
+
+                        warn!("Error reading ring {}: {}", this_ring, e);
+                        if let Some(reason) = reattach_ring(
+                            &mut rings[this_ring],
+                            this_ring,
+                            &mut out,
+                            &mut stats,
+                            shutdown,
+                        ) {
+                            break reason;
+                        }
+                        aligners[this_ring].clear();
+                    }
+                    _ => {
+                        break StopReason::RingError(e);
+                    }
+                }
+            }
+        }
+    };
+    if let Some(progress) = &mut progress {
+        progress.finish(stats.bytes);
+    }
+    // Push out what's buffered unless it's the output that failed:
+
+    let reason = match reason {
+        StopReason::OutputClosed | StopReason::OutputError(_) => reason,
+        _ => match out.flush() {
+            Ok(()) => reason,
+            Err(e) => write_failure(e),
+        },
+    };
+    (reason, stats)
+}
+// Attach ring (index index) again after it went away.  What we've
+// written so far is flushed first so it can't be mistaken for data after
+// the gap.  We keep trying every half second until we manage it or
+// shutdown is set.  None is returned if we reattached, otherwise the
+// reason to stop.
+//
+fn reattach_ring<R: RingReader, W: Write>(
+    ring: &mut R,
+    index: usize,
+    out: &mut W,
+    stats: &mut Stats,
+    shutdown: &AtomicBool,
+) -> Option<StopReason> {
+    if let Err(e) = out.flush() {
+        return Some(write_failure(e));
+    }
+    warn!("Ring {} has gone away; reattaching", index);
+    loop {
+        if shutdown.load(Ordering::Relaxed) {
+            return Some(StopReason::Shutdown);
+        }
+        match ring.reattach() {
+            Ok(()) => {
+                stats.reattaches += 1;
+                warn!(
+                    "Reattached ring {} (reattach {}) after {} bytes; the data is not contiguous across this point",
+                    index, stats.reattaches, stats.bytes
+                );
+                return None;
+            }
+            Err(ringmaster_client::Error::Unimplemented) => {
+                return Some(StopReason::RingError(
+                    ringmaster_client::Error::Unimplemented,
+                ));
+            }
+            Err(e) => {
+                debug!("Reattaching ring {} failed: {}", index, e);
+                thread::sleep(Duration::from_millis(500));
+            }
+        }
+    }
+}
+// If a write that started at start (None if we're not timing writes)
+// blocked for at least options.slow_threshold warn that the downstream is
+// slow.  With options.drop_on_slow the end of the window in which data
+// is dropped is returned.
+//
+fn check_slow(start: Option<Instant>, options: &Options, stats: &Stats) -> Option<Instant> {
+    let (start, threshold) = (start?, options.slow_threshold?);
+    let blocked = start.elapsed();
+    if blocked < threshold {
+        return None;
+    }
+    warn!("downstream slow: blocked {}ms", blocked.as_millis());
+    if options.drop_on_slow {
+        warn!(
+            "dropping data for {}ms ({} gets, {} bytes dropped so far)",
+            threshold.as_millis(),
+            stats.dropped_gets,
+            stats.dropped_bytes
+        );
+        Some(Instant::now() + threshold)
+    } else {
+        None
+    }
+}
+// Turn a failure writing the output into the reason we stop.
+// A broken pipe or reset connection just means our reader has gone away:
+//
+pub(super) fn write_failure(e: io::Error) -> StopReason {
+    if matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+    ) {
+        StopReason::OutputClosed
+    } else {
+        StopReason::OutputError(e)
+    }
+}
@@ -0,0 +1,121 @@
+//!
+//! The drain::histogram module keeps a picture of how the rings deliver
+//! their data: how long there was between successful gets and how big
+//! each get was.  That shows whether a ring gives steady small chunks
+//! or bursts of large ones, which is what --buffer-size and --timeout-ms
+//! should be tuned for.
+//!
+
+use std::fmt;
+use std::time::Instant;
+
+// Bucket i holds values v with 2^i <= v < 2^(i+1) (bucket 0 also holds 0).
+// 64 buckets cover any u64.
+
+const BUCKETS: usize = 64;
+
+///
+/// What one histogram bucket counts: the gets that fell in it and the
+/// bytes those gets delivered.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Bucket {
+    pub gets: u64,
+    pub bytes: u64,
+}
+
+///
+/// Histograms of the successful gets (those that returned data).
+/// intervals is by the microseconds since the previous successful get
+/// (the first get has no interval and isn't in it), sizes by the bytes
+/// the get returned.  Bucket i of each holds values from 2^i up to but
+/// not including 2^(i+1); bucket 0 also holds 0.  The buckets are fixed
+/// arrays so recording a get never allocates.
+/// The Display implementation writes both histograms, leaving out empty
+/// buckets, one line per bucket.
+///
+#[derive(Debug, Clone)]
+pub struct GetHistogram {
+    pub intervals: [Bucket; BUCKETS],
+    pub sizes: [Bucket; BUCKETS],
+    last_get: Option<Instant>,
+}
+impl GetHistogram {
+    pub(super) fn new() -> GetHistogram {
+        GetHistogram {
+            intervals: [Bucket::default(); BUCKETS],
+            sizes: [Bucket::default(); BUCKETS],
+            last_get: None,
+        }
+    }
+    ///
+    /// Record a get of n bytes that completed at now.
+    ///
+    pub fn record(&mut self, n: usize, now: Instant) {
+        let n = n as u64;
+        if let Some(last) = self.last_get {
+            let micros = now.duration_since(last).as_micros() as u64;
+            add(&mut self.intervals[bucket_index(micros)], n);
+        }
+        add(&mut self.sizes[bucket_index(n)], n);
+        self.last_get = Some(now);
+    }
+}
+impl fmt::Display for GetHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Time between gets:")?;
+        write_buckets(f, &self.intervals, |v| {
+            if v >= 1_000_000 {
+                format!("{}s", v / 1_000_000)
+            } else if v >= 1000 {
+                format!("{}ms", v / 1000)
+            } else {
+                format!("{}us", v)
+            }
+        })?;
+        writeln!(f, "Get sizes:")?;
+        write_buckets(f, &self.sizes, |v| {
+            if v >= 1024 * 1024 {
+                format!("{}M", v / (1024 * 1024))
+            } else if v >= 1024 {
+                format!("{}K", v / 1024)
+            } else {
+                format!("{}", v)
+            }
+        })
+    }
+}
+// The bucket a value goes in:
+//
+fn bucket_index(value: u64) -> usize {
+    if value == 0 {
+        0
+    } else {
+        63 - value.leading_zeros() as usize
+    }
+}
+fn add(bucket: &mut Bucket, bytes: u64) {
+    bucket.gets += 1;
+    bucket.bytes += bytes;
+}
+// One line for each bucket that isn't empty, labeled with the smallest
+// value it holds (as unit formats it):
+//
+fn write_buckets<U: Fn(u64) -> String>(
+    f: &mut fmt::Formatter,
+    buckets: &[Bucket],
+    unit: U,
+) -> fmt::Result {
+    for (i, bucket) in buckets.iter().enumerate() {
+        if bucket.gets > 0 {
+            writeln!(
+                f,
+                "  >= {:>6}: {:>10} gets {:>14} bytes",
+                unit(if i == 0 { 0 } else { 1 << i }),
+                bucket.gets,
+                bucket.bytes
+            )?;
+        }
+    }
+    Ok(())
+}
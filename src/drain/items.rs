@@ -0,0 +1,165 @@
+//!
+//! The drain::items module finds the boundaries of NSCLDAQ ring items
+//! in the data read from a ring so that drain can stop after a number
+//! of complete items or write only complete items.
+//!
+//! Each ring item starts with a header of two unsigned 32 bit integers
+//! in the producer's (our) byte order:
+//!
+//! | Offset | Size | Contents                                      |
+//! |--------|------|-----------------------------------------------|
+//! | 0      | 4    | Size of the item in bytes, including the header |
+//! | 4      | 4    | Item type                                     |
+//!
+//! The data must start at an item boundary; that's where a consumer
+//! normally starts as producers put whole items in the ring.
+
+use log::warn;
+
+/// Number of bytes in a ring item header.
+///
+pub const ITEM_HEADER_SIZE: usize = 8;
+
+///
+/// Keeps track of where we are in the stream of items from one ring.
+/// Items, and even their headers, can be split across gets so we
+/// remember a partial header and how much of the current item is left.
+///
+#[derive(Debug, Clone, Default)]
+pub struct ItemCounter {
+    header: [u8; ITEM_HEADER_SIZE],
+    header_bytes: usize,
+    remaining: u64, // Body bytes left in the current item.
+}
+impl ItemCounter {
+    pub fn new() -> ItemCounter {
+        ItemCounter::default()
+    }
+    ///
+    /// Scan the next data from the ring, stopping as soon as limit items
+    /// have been completed.  Returns the number of bytes of data up to where
+    /// we stopped (all of it unless we reached the limit) and the
+    /// number of items completed in those bytes.
+    /// An item whose size is smaller than its header is nonsense; we log
+    /// it and treat it as just a header.
+    ///
+    pub fn scan(&mut self, data: &[u8], limit: u64) -> (usize, u64) {
+        let mut pos = 0;
+        let mut completed = 0;
+        while pos < data.len() && completed < limit {
+            if self.remaining == 0 {
+                // In a header:
+
+                let n = std::cmp::min(ITEM_HEADER_SIZE - self.header_bytes, data.len() - pos);
+                self.header[self.header_bytes..self.header_bytes + n]
+                    .copy_from_slice(&data[pos..pos + n]);
+                self.header_bytes += n;
+                pos += n;
+                if self.header_bytes == ITEM_HEADER_SIZE {
+                    self.header_bytes = 0;
+                    let mut size = [0_u8; 4];
+                    size.copy_from_slice(&self.header[0..4]);
+                    let size = u64::from(u32::from_ne_bytes(size));
+                    if size < ITEM_HEADER_SIZE as u64 {
+                        warn!("Ring item with invalid size {}", size);
+                    }
+                    self.remaining = size.saturating_sub(ITEM_HEADER_SIZE as u64);
+                    if self.remaining == 0 {
+                        completed += 1;
+                    }
+                }
+            } else {
+                // In an item body:
+
+                let n = std::cmp::min(self.remaining, (data.len() - pos) as u64);
+                pos += n as usize;
+                self.remaining -= n;
+                if self.remaining == 0 {
+                    completed += 1;
+                }
+            }
+        }
+        (pos, completed)
+    }
+}
+
+///
+/// Holds back the end of the data from one ring that isn't a whole
+/// ring item so only whole items are passed on (see Options::item_align).
+/// An item can take several gets to arrive; what's held is kept until
+/// it's complete.  We never hold more than the size of the gets, though:
+/// an item that's bigger (or a corrupt header that says it is) is warned
+/// about and passed on as it comes, unaligned, and aligning picks up
+/// again after its end.
+///
+#[derive(Debug, Clone, Default)]
+pub struct ItemAligner {
+    held: Vec<u8>,
+    passed: usize,  // Bytes at the front of held handed out last time.
+    unaligned: u64, // Bytes of an oversized item still to pass as they come.
+}
+impl ItemAligner {
+    pub fn new() -> ItemAligner {
+        ItemAligner::default()
+    }
+    ///
+    /// Add the next data from the ring to what's held and return what
+    /// can be passed on now: the whole items there are (possibly none),
+    /// preceded by any more of an oversized item.  The rest is held
+    /// for next time unless it's more than buffer_size (the size of the
+    /// gets), in which case it's all passed on.
+    ///
+    pub fn align(&mut self, data: &[u8], buffer_size: usize) -> &[u8] {
+        self.held.drain(0..self.passed);
+        self.held.extend_from_slice(data);
+        let through = std::cmp::min(self.unaligned, self.held.len() as u64) as usize;
+        self.unaligned -= through as u64;
+        self.passed = through + whole_items_len(&self.held[through..]);
+        let partial = self.held.len() - self.passed;
+        if partial > buffer_size {
+            let size = item_size(&self.held[self.passed..]).unwrap_or(0);
+            warn!(
+                "Ring item of {} bytes is bigger than the {} byte buffer; writing it unaligned",
+                size, buffer_size
+            );
+            self.unaligned = size.saturating_sub(partial as u64);
+            self.passed = self.held.len();
+        }
+        &self.held[0..self.passed]
+    }
+    ///
+    /// Forget what's held, for when the data that follows won't continue
+    /// it (e.g. after reattaching the ring).
+    ///
+    pub fn clear(&mut self) {
+        self.held.clear();
+        self.passed = 0;
+        self.unaligned = 0;
+    }
+}
+// The size of the item whose header starts data, if the header's all
+// there:
+//
+fn item_size(data: &[u8]) -> Option<u64> {
+    if data.len() < ITEM_HEADER_SIZE {
+        return None;
+    }
+    let mut size = [0_u8; 4];
+    size.copy_from_slice(&data[0..4]);
+    Some(u64::from(u32::from_ne_bytes(size)))
+}
+// How many bytes at the start of data (which starts with an item) are
+// whole items.  As for ItemCounter, an item that claims to be smaller
+// than its header is taken to be just the header.
+//
+fn whole_items_len(data: &[u8]) -> usize {
+    let mut pos = 0;
+    while let Some(size) = item_size(&data[pos..]) {
+        let size = std::cmp::max(size, ITEM_HEADER_SIZE as u64);
+        if size > (data.len() - pos) as u64 {
+            break;
+        }
+        pos += size as usize;
+    }
+    pos
+}
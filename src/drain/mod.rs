@@ -0,0 +1,12 @@
+#[cfg(feature = "async")]
+mod asynchronous;
+mod forward;
+mod histogram;
+mod items;
+mod progress;
+mod queue;
+#[cfg(feature = "async")]
+pub use self::asynchronous::*;
+pub use self::forward::*;
+pub use self::histogram::*;
+pub use self::queue::*;
@@ -0,0 +1,83 @@
+//!
+//! The drain::progress module draws a one line progress display on
+//! stderr for people watching a drain: a bar and percentage when there's
+//! a byte limit, otherwise a spinner and the bytes so far.  The line is
+//! redrawn in place (with a carriage return) at most a few times a
+//! second.  Whether stderr is a terminal is for the caller to decide.
+//!
+
+use std::io;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+// How often the line is redrawn and how wide the bar is:
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+const BAR_WIDTH: usize = 40;
+
+const SPINNER: &[u8] = b"|/-\\";
+
+pub(super) struct Progress {
+    limit: Option<u64>,
+    last_draw: Option<Instant>,
+    spins: usize,
+}
+impl Progress {
+    // limit is the byte limit we're heading for, if there is one.
+    //
+    pub(super) fn new(limit: Option<u64>) -> Progress {
+        Progress {
+            limit,
+            last_draw: None,
+            spins: 0,
+        }
+    }
+    // Redraw the line for bytes forwarded if it's been long enough since
+    // the last time:
+    //
+    pub(super) fn update(&mut self, bytes: u64) {
+        let now = Instant::now();
+        if self
+            .last_draw
+            .is_some_and(|last| now.duration_since(last) < REDRAW_INTERVAL)
+        {
+            return;
+        }
+        self.last_draw = Some(now);
+        self.spins += 1;
+        self.draw(bytes);
+    }
+    // Draw the final state and end the line so what follows starts
+    // on a line of its own.
+    //
+    pub(super) fn finish(&mut self, bytes: u64) {
+        self.draw(bytes);
+        eprintln!();
+    }
+    // Errors writing stderr aren't worth stopping for:
+    //
+    fn draw(&self, bytes: u64) {
+        let line = match self.limit {
+            Some(limit) if limit > 0 => {
+                let fraction = (bytes as f64 / limit as f64).min(1.0);
+                let filled = (fraction * BAR_WIDTH as f64) as usize;
+                format!(
+                    "[{}{}] {:>3}% {}/{} bytes",
+                    "#".repeat(filled),
+                    ".".repeat(BAR_WIDTH - filled),
+                    (fraction * 100.0) as u32,
+                    bytes,
+                    limit
+                )
+            }
+            _ => format!(
+                "{} {} bytes",
+                SPINNER[self.spins % SPINNER.len()] as char,
+                bytes
+            ),
+        };
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r{}\x1b[K", line);
+        let _ = stderr.flush();
+    }
+}
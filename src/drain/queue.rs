@@ -0,0 +1,228 @@
+//!
+//! The drain::queue module decouples reading the rings from writing the
+//! output.  drain_queued runs the usual drain loop but the data from
+//! each get is put on a bounded queue and a separate thread writes it
+//! to the output.  A momentarily slow output then doesn't stop us
+//! reading the rings until the queue fills.
+//!
+
+use super::forward::{drain, write_failure, Checksum, Options, Stats, StopReason};
+use crate::ringmaster_client::RingReader;
+use log::warn;
+use std::io;
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+// The writer thread's failure, if it had one.  The drain side only sees
+// the queue close so it picks the error up from here.
+
+type WriteError = Arc<Mutex<Option<io::Error>>>;
+
+// What the drain loop writes to.  Writes are collected and each flush
+// (drain flushes after every get when queuing) puts what was collected
+// on the queue as one message, so a message never holds part of a
+// frame.  If drop_when_full is set and the queue is full, the message
+// is dropped rather than waiting for room.  The writer thread sends the
+// buffers it's done with back through free so they can be reused rather
+// than allocating a new one for every message.  Once pool buffers have
+// been allocated we wait for one to come back instead of allocating
+// another (except when dropping, which mustn't wait on the writer).
+
+struct QueueWriter {
+    pending: Vec<u8>,
+    queue: mpsc::SyncSender<Vec<u8>>,
+    free: mpsc::Receiver<Vec<u8>>,
+    allocations: u64,
+    pool: usize,
+    error: WriteError,
+    drop_when_full: bool,
+    dropping: bool, // Warned about the current run of drops.
+    dropped_gets: u64,
+    dropped_bytes: u64,
+}
+impl QueueWriter {
+    fn closed(&self) -> io::Error {
+        self.error
+            .lock()
+            .unwrap()
+            .take()
+            .unwrap_or_else(|| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+    // A buffer for the next message: a free one if there is one.
+    //
+    fn spare(&mut self) -> Vec<u8> {
+        let waiting = self.pool > 0 && !self.drop_when_full;
+        match self.free.try_recv() {
+            Ok(buffer) => return buffer,
+            Err(mpsc::TryRecvError::Empty) if waiting && self.allocations >= self.pool as u64 => {
+                if let Ok(buffer) = self.free.recv() {
+                    return buffer;
+                }
+            }
+            Err(_) => {}
+        }
+        self.allocations += 1;
+        Vec::new()
+    }
+}
+impl Write for QueueWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let message = std::mem::take(&mut self.pending);
+        if !self.drop_when_full {
+            self.queue.send(message).map_err(|_| self.closed())?;
+            self.pending = self.spare();
+            return Ok(());
+        }
+        match self.queue.try_send(message) {
+            Ok(()) => {
+                self.dropping = false;
+                self.pending = self.spare();
+                Ok(())
+            }
+            Err(mpsc::TrySendError::Full(mut message)) => {
+                self.dropped_gets += 1;
+                self.dropped_bytes += message.len() as u64;
+                if !self.dropping {
+                    self.dropping = true;
+                    warn!(
+                        "output queue full; dropping data ({} gets, {} bytes dropped so far)",
+                        self.dropped_gets, self.dropped_bytes
+                    );
+                }
+                message.clear();
+                self.pending = message;
+                Ok(())
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => Err(self.closed()),
+        }
+    }
+}
+
+///
+/// Like drain, but the output is written by a separate thread, fed
+/// through a queue of at most options.queue_depth gets (1 if it's None).
+/// When the queue is full we wait for room, unless options.drop_on_slow
+/// is set, in which case the get is dropped (and counted in the stats'
+/// dropped_ fields) so the rings keep being drained.
+/// Writes that block for at least options.slow_threshold are warned
+/// about by the writer thread.  With options.checksum, the checksum
+/// covers what the writer thread actually wrote, so dropped data is not
+/// included.
+/// When we stop, the data already queued is written and the output
+/// flushed before we return, unless writing the output failed.
+/// Buffers are recycled: at most options.buffer_pool of them (by default
+/// two more than the queue depth) are allocated and once they all are,
+/// we wait for the writer to finish with one.  A pool of 0 turns
+/// recycling off.  When dropping on a slow output we never wait, so more
+/// may be allocated.  The stats' buffer_allocations says how many were.
+///
+pub fn drain_queued<R: RingReader, W: Write + Send>(
+    rings: &mut [R],
+    out: W,
+    options: &Options,
+    shutdown: &AtomicBool,
+) -> (StopReason, Stats) {
+    let depth = std::cmp::max(options.queue_depth.unwrap_or(1), 1);
+    let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(depth);
+    let pool = options.buffer_pool.unwrap_or(depth + 2);
+    let (free_sender, free) = mpsc::sync_channel::<Vec<u8>>(pool);
+    let error = WriteError::default();
+    let mut queue = QueueWriter {
+        pending: Vec::new(),
+        queue: sender,
+        free,
+        allocations: 1,
+        pool,
+        error: Arc::clone(&error),
+        drop_when_full: options.drop_on_slow,
+        dropping: false,
+        dropped_gets: 0,
+        dropped_bytes: 0,
+    };
+    // The checksum is kept by the writer rather than the drain loop:
+
+    let mut drain_options = options.clone();
+    drain_options.checksum = false;
+
+    thread::scope(|scope| {
+        let writer = scope.spawn(|| write_queue(receiver, free_sender, out, options, error));
+        let (reason, mut stats) = drain(rings, &mut queue, &drain_options, shutdown);
+        stats.dropped_gets += queue.dropped_gets;
+        stats.dropped_bytes += queue.dropped_bytes;
+        stats.buffer_allocations = queue.allocations;
+        drop(queue); // Closes the queue so the writer finishes.
+
+        let (checksum, result) = writer.join().unwrap();
+        stats.checksum = checksum;
+        let reason = match (reason, result) {
+            (StopReason::OutputClosed, _) => StopReason::OutputClosed,
+            (StopReason::OutputError(e), _) => StopReason::OutputError(e),
+            (reason, Ok(())) => reason,
+            (_, Err(e)) => write_failure(e),
+        };
+        (reason, stats)
+    })
+}
+// The writer thread: write the messages from the queue to out until the
+// queue is closed and empty.  out is flushed whenever the queue runs
+// dry.  Written buffers go back through free unless it's full.  A
+// failure is saved in error (so the drain side can report it when its
+// sends fail) and returned along with the checksum, if kept.
+//
+fn write_queue<W: Write>(
+    receiver: mpsc::Receiver<Vec<u8>>,
+    free: mpsc::SyncSender<Vec<u8>>,
+    mut out: W,
+    options: &Options,
+    error: WriteError,
+) -> (Option<Checksum>, io::Result<()>) {
+    let mut checksum = if options.checksum {
+        Some(Checksum::new())
+    } else {
+        None
+    };
+    let result = (|| {
+        loop {
+            let mut message = match receiver.try_recv() {
+                Ok(message) => message,
+                Err(mpsc::TryRecvError::Empty) => {
+                    out.flush()?;
+                    match receiver.recv() {
+                        Ok(message) => message,
+                        Err(_) => break,
+                    }
+                }
+                Err(mpsc::TryRecvError::Disconnected) => break,
+            };
+            let start = Instant::now();
+            out.write_all(&message)?;
+            if let Some(threshold) = options.slow_threshold {
+                let blocked = start.elapsed();
+                if blocked >= threshold {
+                    warn!("downstream slow: blocked {}ms", blocked.as_millis());
+                }
+            }
+            if let Some(checksum) = &mut checksum {
+                checksum.update(&message);
+            }
+            message.clear();
+            let _ = free.try_send(message);
+        }
+        out.flush()
+    })();
+    if let Err(e) = &result {
+        *error.lock().unwrap() = Some(io::Error::new(e.kind(), e.to_string()));
+    }
+    (checksum, result)
+}
@@ -0,0 +1,101 @@
+//!
+//! The framing::frame module defines the framing used when
+//! ringtostdout multiplexes the data from several rings onto one
+//! output stream (or when --frame is used with a single ring).
+//!
+//! Each chunk of data read from a ring is preceded by an 8 byte
+//! header:
+//!
+//! | Offset | Size | Contents                                          |
+//! |--------|------|---------------------------------------------------|
+//! | 0      | 4    | Ring index - position of the ring in the --ring list |
+//! | 4      | 4    | Number of data bytes that follow the header       |
+//!
+//! Both fields are unsigned 32 bit little-endian integers.  The data
+//! bytes immediately follow the header.  A downstream demultiplexer can
+//! use read_frame to split the stream back into its rings.
+
+use std::io;
+use std::io::Read;
+
+/// Number of bytes in a frame header.
+///
+pub const FRAME_HEADER_SIZE: usize = 8;
+
+///
+/// A decoded frame header:
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameHeader {
+    pub ring_index: u32,
+    pub length: u32,
+}
+
+///
+/// Produce the bytes of the header for a frame of length data bytes
+/// read from the ring at ring_index.
+///
+pub fn encode_header(ring_index: u32, length: u32) -> [u8; FRAME_HEADER_SIZE] {
+    let mut header = [0_u8; FRAME_HEADER_SIZE];
+    header[0..4].copy_from_slice(&ring_index.to_le_bytes());
+    header[4..8].copy_from_slice(&length.to_le_bytes());
+    header
+}
+///
+/// Decode a frame header from the first FRAME_HEADER_SIZE bytes
+/// of bytes.  None is returned if there are not enough bytes.
+///
+pub fn decode_header(bytes: &[u8]) -> Option<FrameHeader> {
+    if bytes.len() < FRAME_HEADER_SIZE {
+        None
+    } else {
+        let mut index = [0_u8; 4];
+        let mut length = [0_u8; 4];
+        index.copy_from_slice(&bytes[0..4]);
+        length.copy_from_slice(&bytes[4..8]);
+        Some(FrameHeader {
+            ring_index: u32::from_le_bytes(index),
+            length: u32::from_le_bytes(length),
+        })
+    }
+}
+///
+/// Read the next frame from a framed stream.  On success, the
+/// header and the frame's data are returned.  Ok(None) means the
+/// stream ended cleanly between frames; a stream that ends inside a
+/// frame gives an UnexpectedEof error.
+///
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Option<(FrameHeader, Vec<u8>)>> {
+    let mut header_bytes = [0_u8; FRAME_HEADER_SIZE];
+    let mut got = 0;
+    while got < FRAME_HEADER_SIZE {
+        match reader.read(&mut header_bytes[got..]) {
+            Ok(0) => {
+                if got == 0 {
+                    return Ok(None);
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "stream ended inside a frame header",
+                ));
+            }
+            Ok(n) => got += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let header = decode_header(&header_bytes).expect("BUG - full header was read");
+
+    // The length isn't trusted with an allocation; the data grows as it
+    // arrives:
+
+    let mut data = Vec::new();
+    reader.take(header.length as u64).read_to_end(&mut data)?;
+    if data.len() < header.length as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "stream ended inside a frame",
+        ));
+    }
+    Ok(Some((header, data)))
+}
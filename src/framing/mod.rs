@@ -0,0 +1,4 @@
+mod frame;
+mod stream_header;
+pub use self::frame::*;
+pub use self::stream_header::*;
@@ -0,0 +1,107 @@
+//!
+//! The framing::stream_header module defines the header ringtostdout
+//! writes at the very start of its output when --with-header is given,
+//! so downstream programs can tell what the stream is.  Without
+//! --with-header there's no header and the stream is pure ring data.
+//!
+//! The header is:
+//!
+//! | Offset | Size | Contents                                            |
+//! |--------|------|-----------------------------------------------------|
+//! | 0      | 8    | Magic - the ASCII characters RING2OUT               |
+//! | 8      | 2    | Version of this layout (currently 1)                |
+//! | 10     | 2    | n - number of bytes in the ring name                |
+//! | 12     | n    | Ring name (UTF-8).  With several rings, their names |
+//! |        |      | comma separated in frame ring index order           |
+//! | 12+n   | 8    | Time the stream started, seconds since the Unix epoch |
+//!
+//! The integers are unsigned little-endian.  The ring data (framed or not)
+//! immediately follows.  read_stream_header parses the header.
+
+use std::io;
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The magic bytes that start a stream header.
+///
+pub const STREAM_MAGIC: [u8; 8] = *b"RING2OUT";
+
+/// The version of the stream header we write and understand.
+///
+pub const STREAM_HEADER_VERSION: u16 = 1;
+
+///
+/// A stream header's contents:
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamHeader {
+    pub version: u16,
+    pub ring_name: String,
+    pub timestamp: u64,
+}
+impl StreamHeader {
+    ///
+    /// A current version header for ring_name, timestamped now.
+    ///
+    pub fn new(ring_name: &str) -> StreamHeader {
+        StreamHeader {
+            version: STREAM_HEADER_VERSION,
+            ring_name: String::from(ring_name),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        }
+    }
+}
+///
+/// Write header to writer.  A ring name longer than 65535 bytes can't be
+/// described and gives an InvalidInput error.
+///
+pub fn write_stream_header(writer: &mut impl Write, header: &StreamHeader) -> io::Result<()> {
+    let name = header.ring_name.as_bytes();
+    if name.len() > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the ring name is too long for a stream header",
+        ));
+    }
+    let mut bytes = Vec::<u8>::with_capacity(20 + name.len());
+    bytes.extend_from_slice(&STREAM_MAGIC);
+    bytes.extend_from_slice(&header.version.to_le_bytes());
+    bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(name);
+    bytes.extend_from_slice(&header.timestamp.to_le_bytes());
+    writer.write_all(&bytes)
+}
+///
+/// Read the stream header from the start of a stream.  It's an
+/// InvalidData error if the stream doesn't start with a header we
+/// understand (wrong magic, a version other than STREAM_HEADER_VERSION
+/// or a ring name that's not UTF-8).
+///
+pub fn read_stream_header(reader: &mut impl Read) -> io::Result<StreamHeader> {
+    let mut fixed = [0_u8; 12];
+    reader.read_exact(&mut fixed)?;
+    if fixed[0..8] != STREAM_MAGIC {
+        return Err(invalid("the stream does not start with a stream header"));
+    }
+    let version = u16::from_le_bytes([fixed[8], fixed[9]]);
+    if version != STREAM_HEADER_VERSION {
+        return Err(invalid("unsupported stream header version"));
+    }
+    let mut name = vec![0_u8; u16::from_le_bytes([fixed[10], fixed[11]]) as usize];
+    reader.read_exact(&mut name)?;
+    let ring_name =
+        String::from_utf8(name).map_err(|_| invalid("the stream header ring name is not UTF-8"))?;
+    let mut timestamp = [0_u8; 8];
+    reader.read_exact(&mut timestamp)?;
+    Ok(StreamHeader {
+        version,
+        ring_name,
+        timestamp: u64::from_le_bytes(timestamp),
+    })
+}
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
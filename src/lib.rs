@@ -6,23 +6,301 @@
 //!
 //! ## Usage
 //!
-//! ```
+//! ```text
 //! ringtostdout option...
 //! ```
 //!
 //! Where the options are:
 //!
 //! *   --directory - the directory with the ringbuffers.  If not
-//! present on the command line defaults to /dev/shm (suitable for
-//! linux)
+//!     present on the command line, the RINGTOSTDOUT_DIR environment
+//!     variable is used or, if that's not set either, /dev/shm (suitable for
+//!     linux)
+//! *   --mkdir - If present and the --directory does not exist, it is
+//!     created, along with any missing parents, using the default
+//!     permissions (as limited by the umask).  Only directories are
+//!     created, the ring buffer file belongs to its producer.
+//! *   --no-directory-check - Normally the --directory must exist and be
+//!     readable (listable) or we exit with a usage error.  With this the
+//!     check is skipped and any problem is left to mapping the ring, for
+//!     unusual permission setups where a ring file can be mapped from a
+//!     directory that can't be listed.
 //! *   --ring - name of the ring buffer file in that directory
-//! we'll prepend the directory path.
+//!     we'll prepend the directory path.  It must be a plain file name;
+//!     names with a / or that are . or .. are rejected.  This can be given more than once
+//!     to take data from several rings.  The rings are then read
+//!     round-robin and each chunk of data is framed as described in the
+//!     framing module.  If no --ring is given, the RINGTOSTDOUT_RING
+//!     environment variable (one ring name) is used.
+//! *   --no-canonicalize - By default symbolic links in the --directory and
+//!     ring path are resolved so the file we map and the ring name we give
+//!     the ringmaster are the ring's real ones (a link to a missing file is
+//!     an error).  With this the path is used as given.
+//! *   --frame - Frame the data even if there's only one ring.  Without this,
+//!     data from a single ring is sent as is.
+//! *   --host - If present, the ring is in this remote host.  The remote
+//!     ringmaster is asked to send us the ring's data and --directory is
+//!     ignored.
 //! *   --port - Portmanager (not ringmaster) listen port, if not present,
-//! defaults to 30000 the standard.
+//!     defaults to 30000 the standard.
+//! *   --portman-host - If present, the port manager in this host is asked for
+//!     the ringmaster's port.  By default the local port manager is asked or,
+//!     with --host, the one in the ring's host.
+//! *   --portman-retries - If present, when the port manager can't be
+//!     reached (e.g. it's still starting) it's tried again up to this many
+//!     times, waiting 100ms and then twice as long each time, up to 5
+//!     seconds.  A port manager that answers but has no ringmaster isn't
+//!     asked again (see --retry for that).  Each --retry attempt does this.
+//! *   --buffer-size - Size of the chunks read from the ring buffer.  The
+//!     value can have a K, M or G suffix (e.g. 512K).  Defaults to 1M.
+//! *   --timeout-ms - Milliseconds each read from the ring waits for data
+//!     before timing out (default 1).  0 polls the ring without waiting,
+//!     which is a busy poll and burns CPU when the ring is idle.
+//! *   --bulk - If present, each read waits (up to a quarter second, in
+//!     place of --timeout-ms) for a whole --buffer-size of data.  That's
+//!     the efficient way to pull a lot of data out, e.g. with --max-bytes,
+//!     but data can wait in the ring up to that long before being
+//!     forwarded, and exiting on a signal can take that long too.
+//! *   --max-bytes - If present, exit successfully after exactly this many
+//!     bytes have been forwarded (with --item-align, up to this many).  K, M
+//!     and G suffixes are allowed.
+//! *   --items - If present, exit after forwarding this many complete NSCLDAQ
+//!     ring items; only whole items are written.  The data must be ring items
+//!     starting at an item boundary (as it is when we attach to a ring).  The
+//!     number of items forwarded is logged on exit.  If both this and
+//!     --max-bytes are given, whichever limit is reached first applies.
+//! *   --item-align - If present, only whole NSCLDAQ ring items are written,
+//!     so each write (and, with several rings, each frame) is whole items.
+//!     Items bigger than --buffer-size are warned about and forwarded
+//!     unaligned.  As for --items the data must start at an item boundary.
+//! *   --flush-policy - When the output is flushed: each (after every read
+//!     from the rings; lowest latency, most writes), idle (only when the
+//!     rings go quiet; best throughput, the default) or bytes:N (once N bytes,
+//!     K, M and G allowed, have been written since the last flush; bounds
+//!     how much data is held back).  The output is always flushed when the
+//!     rings go quiet and when we exit.
+//! *   --stats-interval - If present, every this many seconds a line with
+//!     the bytes, gets and timeouts (reads that found no data) so far and
+//!     the recent data rate is written to stderr.
+//! *   --json-stats - If present, the --stats-interval lines are instead
+//!     single line JSON objects e.g.
+//!     {"bytes":1048576,"gets":12,"timeouts":250,"rate_mbps":0.349,"uptime_s":3.001}
+//!     where rate_mbps is MBytes/sec since the last line.
+//! *   --progress - If present and stderr is a terminal, a progress line is
+//!     kept up to date on stderr: a bar and percentage of --max-bytes or,
+//!     without --max-bytes, a spinner and the bytes forwarded so far.  When
+//!     stderr isn't a terminal this is ignored.
+//! *   --output - If present, the data are written to this file (or FIFO)
+//!     instead of stdout.  A file is created or truncated.
+//! *   --allow-tty - Without --output, --connect or --unix-socket the data
+//!     goes to stdout, and if that's a terminal (the redirect was
+//!     forgotten) we refuse to start (exit status 1) as binary data would
+//!     make a mess of it.  This lets it through anyway.
+//! *   --append - Requires --output.  An existing output file is appended to
+//!     rather than truncated (it's still created if need be).
+//! *   --tee - If present, a copy of everything written to the output
+//!     (stdout, --output or a socket) is also written to this file, which is
+//!     created or truncated.  Can be given more than once.  If writing a tee
+//!     file fails that's logged and the output carries on without it.
+//! *   --sync-interval - Requires --output.  Every this many seconds the
+//!     output file's data is synced to disk (fdatasync, from a separate
+//!     thread) so a crash loses at most about that long's worth of capture.
+//!     Nothing is done if the output isn't a regular file.
+//! *   --compress - Requires --output.  none (the default), gzip or zstd: the
+//!     output file is compressed as it's written.  The compressed stream is
+//!     only completed when we exit cleanly.
+//! *   --with-header - If present, the output starts with a header giving
+//!     the ring name(s) and start time (see framing::StreamHeader for the
+//!     layout and framing::read_stream_header to parse it) before any ring
+//!     data.  Without it the output is just the ring data, as stdintoring
+//!     expects.
+//! *   --unix-socket - If present, the data are written to this Unix domain
+//!     (stream) socket instead of stdout.  By default we connect to a socket
+//!     the reader is already listening on.  Can't be used with --output.
+//! *   --unix-listen - Requires --unix-socket.  We create the socket, wait for
+//!     a single reader to connect and then remove the socket file.  The
+//!     path must not already exist.
+//!     Either way, the reader closing its end is a normal end, just like
+//!     a closed pipe.
+//! *   --connect - If present, HOST:PORT to make a TCP connection to and write
+//!     the data to instead of stdout.  Small writes are sent immediately
+//!     (TCP_NODELAY).  Can't be used with --output or --unix-socket.  The
+//!     connection being closed is a normal end, like a closed pipe (but see
+//!     --reconnect).
+//! *   --reconnect - Requires --connect.  If the connection is lost we
+//!     connect again, retrying every half second, and carry on.  Data in
+//!     flight when the connection went is lost.
+//! *   --retry - If the ring, the ringmaster or the port manager is not there
+//!     yet, retry the attach this many times with exponentially increasing
+//!     delays.
+//! *   --print-slot - Once attached, write the consumer slot to stderr as
+//!     slot=N.  Remote rings print slot=remote as their slot is in the
+//!     remote host.
+//! *   --ringmaster-timeout - Seconds to wait for the ringmaster to accept
+//!     our connection and reply to our requests (default 5).  The same
+//!     limit applies to the port manager in a remote ring's host.
+//! *   --ringmaster-index - If the port manager has several ringmasters
+//!     registered, the one to use, counting from 0 in the order the port
+//!     manager lists them.  Without this, having several is an error that
+//!     lists their ports.
+//! *   --ringmaster-service - The service name the ringmaster is registered
+//!     under with the port manager (default RingMaster), for test harnesses
+//!     and non-standard deployments.
+//! *   --ringmaster-socket - Talk to the local ringmaster over this Unix
+//!     domain socket rather than TCP, for deployments where it listens on
+//!     one.  A leading @ makes the rest a name in Linux's abstract socket
+//!     namespace (e.g. @ringmaster), otherwise it's the socket file's path.
+//!     The port manager isn't asked for the ringmaster then; remote rings
+//!     are still reached over TCP.
+//! *   --slot - The consumer slot to attach to local rings in, rather than
+//!     the first free one.  Attaching fails if the ring has no such slot
+//!     or it's taken, by another consumer or as far as the ringmaster is
+//!     concerned.
+//! *   --keepalive - If present, TCP keepalive is turned on for our
+//!     ringmaster connections: when one has been idle this many seconds it's
+//!     probed, and again every this many seconds.  If the ringmaster (or its
+//!     host) has gone we exit with status 3 (or, with --reattach, attach
+//!     again).
+//! *   --idle-sleep - If present, once the rings have been idle for a while
+//!     (see --idle-after) we sleep between reads, starting at 1ms and
+//!     doubling each time up to this many milliseconds, until data arrives.
+//!     This cuts the CPU used by an idle ring.
+//! *   --idle-after - Requires --idle-sleep.  How many reads in a row must
+//!     time out before the idle sleeping starts (default 100).
+//! *   --exit-on-idle - If present, once a ring has had no data for this many
+//!     seconds and has no producer, it has ended.  When all rings have
+//!     ended the program exits successfully.  For remote rings we can't
+//!     see the producer so only the idle time counts.
+//! *   --rate-limit - If present, the most bytes per second written to the
+//!     output, e.g. 10M (K, M and G are powers of 1024).  The rate
+//!     is enforced over about a tenth of a second so short bursts are
+//!     smoothed without adding much latency.
+//! *   --slow-ms - If present, writes to the output that block for at least
+//!     this many milliseconds (a slow downstream reader) log a warning.
+//! *   --drop-on-slow - Requires --slow-ms.  After a slow write, the data
+//!     read from the rings in the next --slow-ms milliseconds is thrown away
+//!     rather than written.  This keeps the rings drained so producers don't
+//!     stall behind a slow reader, *but data is lost*.
+//! *   --queue-depth - If present, the output is written by a separate
+//!     thread and up to this many reads from the rings are queued for it, so
+//!     a briefly slow output doesn't hold up reading the rings.  When the
+//!     queue is full we wait for room or, with --drop-on-slow, drop the data.
+//! *   --buffer-pool - Requires --queue-depth.  How many buffers are
+//!     recycled between reading the ring(s) and the output thread (default
+//!     two more than --queue-depth; 0 turns recycling off).
+//! *   --buffer-count - Shorthand for the two above with N buffers (at least
+//!     2; 2 is double buffering): --queue-depth N-1 --buffer-pool N.  A
+//!     burst of up to N-1 reads is absorbed while a slow output catches up,
+//!     at the cost of up to N times --buffer-size of memory.
+//! *   --skip-backlog - If present, the data already in the ring(s) when we
+//!     attach is read and thrown away, only data that arrives after that is
+//!     forwarded.  The number of bytes skipped is logged.
+//! *   --reattach - If present, when a local ring is deleted or recreated, or
+//!     a remote ringmaster closes our connection, we wait for the ring to
+//!     come back and attach to it again rather than exiting.  Each
+//!     reattach is logged; data on either side of it need not be
+//!     contiguous.
+//! *   --checksum - If present, a CRC32 and count of the bytes written to the
+//!     output (including any frame headers) are kept and printed to stderr as
+//!     bytes=N crc32=0x... when the program exits cleanly.
+//! *   --latency-histogram - If present, the time between reads that got data
+//!     and the size of each are recorded and, when the program exits cleanly,
+//!     histograms of both (power of 2 buckets with the reads and bytes in
+//!     each) are printed to stderr.  Useful for tuning --buffer-size and
+//!     --timeout-ms.
+//! *   --first-byte-timeout - If present, when no data at all has arrived
+//!     this many seconds after attaching, exit with status 5.  Once data has
+//!     arrived it no longer applies.  Handy for health checks that expect a
+//!     live producer.
+//! *   --heartbeat - If present, whenever no data has arrived for this many
+//!     seconds a line "heartbeat: idle Ns, M bytes total" is logged so it's
+//!     clear we're alive.
+//! *   --watchdog - If present, when a write to the output has been blocked
+//!     for this many seconds, downstream is taken to be stuck and the
+//!     program logs an error and exits with status 4 (as for other output
+//!     errors).  Rings that are idle never trigger it as nothing is being
+//!     written.
+//! *   --require-producer - If present, a ring that has no producer when we
+//!     attach is an error (exit status 2, after any --retry attempts) rather
+//!     than something to wait for.  Remote rings can't be checked so this
+//!     only warns for them.
+//! *   --stop-on-stdin-eof - If present, closing our stdin stops us cleanly,
+//!     just like SIGINT or SIGTERM.  What's read from stdin is thrown away;
+//!     if it's already at end of file (e.g. /dev/null) we stop straight away.
+//! *   --list - If present, list the ring buffers in the --directory to
+//!     stderr (name, data size, producer pid and consumers/slots) and exit;
+//!     --ring is then not needed.  Files that aren't ring buffers are
+//!     skipped and counted.
+//! *   --discover - If present, ask the ringmaster (LIST) which rings it
+//!     knows and list them to stderr (name, data size, producer pid and
+//!     consumers), then exit.  That's the ringmaster's view, which can
+//!     include rings that aren't in --directory.  --ring is not needed.
+//! *   --dry-run - If present, attach to the ring(s), report whether that
+//!     worked and exit without forwarding any data.  The exit status is 0 if
+//!     all rings could be attached.  The output is not opened.
+//! *   --verbose (-v) - Diagnostics are logged to stderr (never stdout).  By
+//!     default informational messages and worse are logged; -v adds debug
+//!     messages and -vv trace messages.  If RUST_LOG is set it overrides this.
+//! *   --quiet-timeouts - If present, reads that time out aren't logged.
+//!     Otherwise they are debug messages, so with -v an idle ring logs one
+//!     per --timeout-ms, which can bury what you're looking for.
+//! *   --log-file - If present, diagnostics are appended to this file
+//!     (created if need be) instead of going to stderr, which is then left
+//!     to the --json-stats and --progress lines.  Failing to open it is a
+//!     usage error.
+//! *   --stderr-prefix - Each diagnostic line (log messages, including the
+//!     --stats-interval and --heartbeat lines) starts with this and a colon,
+//!     so the merged stderr of many instances can be sorted out.  By default
+//!     it's the ring name(s), comma separated; an empty prefix turns it off.
+//!     Machine readable lines are left as they are.
+//! *   --pidfile - If present, once the ring(s) are attached our process id
+//!     is written to this file (created or truncated), for supervisors.  It's
+//!     removed when we exit; not being able to write it is an error (exit
+//!     status 1) and nothing is forwarded.  Not used with --dry-run.
+//! *   --name-suffix - If present, a name (letters, digits, ., _ and -) for
+//!     this instance, for when several consume the same ring.  The process
+//!     title becomes ring2stdout-NAME (followed by any --comment), every log
+//!     line is tagged [NAME] and the --json-stats lines get a "name" field.
+//! *   --version (-V) - Print our version along with those of the
+//!     nscldaq_ringbuffer and portman_client crates we were built with, and
+//!     exit.
 //! *   --comment - If present, the supplied text is used to construct
-//! a header visible in system displays of processes.  The ringmaster uses this
-//! to indicate where the ringtostdout programs it spawns off will be sending
-//! its data (ringmaster will have arranged for the stdout of ringtostdout to
-//! be a socket to a client (which will get spawned off to be an stdintoring)).
+//!     a header visible in system displays of processes.  The ringmaster uses this
+//!     to indicate where the ringtostdout programs it spawns off will be sending
+//!     its data (ringmaster will have arranged for the stdout of ringtostdout to
+//!     be a socket to a client (which will get spawned off to be an stdintoring)).
+//!     Control characters (e.g. newlines) in the comment are replaced by
+//!     spaces and it's truncated to 128 characters, with a warning.
+//!
+//! However the program ends cleanly (the rings end, a limit is reached,
+//! a signal, stdin closing or the output being closed), a summary line with the bytes,
+//! gets and timeouts, the run time and the average rate is logged.
+//!
+//! SIGINT and SIGTERM stop us cleanly, including while we wait for a
+//! --unix-listen reader or to --retry attaching.  If a write to the
+//! output is stuck, a second signal exits right away (status 128 +
+//! the signal number).
+//!
+//! Command line options always take precedence over the environment
+//! variables, which take precedence over the built in defaults.  Empty
+//! environment variables are ignored.
+//!
+//! ## Library use
+//!
+//! The forwarding loop is also available to other programs.
+//! drain::run_to_writer attaches to a local ring and drains it into
+//! any Write, drain::drain does the same for already attached
+//! ringmaster_client::RingClient objects or anything else that implements
+//! ringmaster_client::RingReader.
+//! With the async feature, drain::run_to_writer_async is run_to_writer for
+//! tokio programs: it forwards a local ring to a tokio AsyncWrite, reading
+//! the ring on tokio's blocking thread pool.
+//!
+//! examples/soak.rs drives drain::drain (or drain_queued) with a local
+//! producer at a chosen rate and reports the throughput and latency
+//! percentiles, for measuring the effect of the drain options:
+//! `cargo run --release --example soak -- --help`.
 
+pub mod drain;
+pub mod framing;
 pub mod ringmaster_client;
@@ -1,21 +1,97 @@
-pub mod ringmaster_client;
 use clap::{App, Arg};
-use proctitle;
+use log::{debug, error, info, warn};
+use nscldaq_ringtostdout::drain;
+use nscldaq_ringtostdout::framing;
+use nscldaq_ringtostdout::ringmaster_client;
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::io;
-use std::io::Write;
+use std::io::{IsTerminal, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::path;
 use std::process;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Exit codes for the program.  The ringmaster (or whoever started us)
+// can use these to tell why we exited:
+
+const EXIT_OK: i32 = 0; // Clean end of data.
+const EXIT_USAGE: i32 = 1; // Invalid command line arguments.
+const EXIT_ATTACH_FAILED: i32 = 2; // Could not become a consumer of the ring.
+const EXIT_RING_ERROR: i32 = 3; // Reading the ring failed after attaching.
+const EXIT_OUTPUT_ERROR: i32 = 4; // Writing the output failed.
+const EXIT_NO_DATA: i32 = 5; // No data within --first-byte-timeout.
+
+// What --version reports: our version and those of the crates that
+// decide what ring buffers and port managers we can talk to.
+
+const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (nscldaq_ringbuffer ",
+    env!("RINGBUFFER_VERSION"),
+    ", portman_client ",
+    env!("PORTMAN_CLIENT_VERSION"),
+    ")"
+);
+
+// The most characters of --comment we put in the process title:
+
+const MAX_COMMENT_CHARS: usize = 128;
+
+// --compress: how the output is compressed, if at all.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
 
 /// These are the program arguments processed by clap:
 ///
 #[derive(Debug)]
 struct ProgramArguments {
-    directory: String,
-    ring_name: String,
+    directory: path::PathBuf,
+    ring_names: Vec<OsString>,
+    host: Option<String>,
     portman: u16,
+    portman_host: Option<String>,
+    portman_retries: u32,
     comment: String,
+    name_suffix: Option<String>,
+    options: drain::Options,
+    output: Option<String>,
+    unix_socket: Option<String>,
+    unix_listen: bool,
+    append: bool,
+    tee: Vec<String>,
+    sync_interval: Option<Duration>,
+    compress: Compression,
+    connect: Option<String>,
+    reconnect: bool,
+    with_header: bool,
+    pidfile: Option<String>,
+    watchdog: Option<Duration>,
+    require_producer: bool,
+    stop_on_stdin_eof: bool,
+    retries: u32,
+    print_slot: bool,
+    ringmaster_timeout: Duration,
+    ringmaster_index: Option<usize>,
+    ringmaster_service: String,
+    ringmaster_socket: Option<ringmaster_client::RingMasterSocket>,
+    slot: Option<u32>,
+    keepalive: Option<Duration>,
+    dry_run: bool,
+    allow_tty: bool,
+    canonicalize: bool,
+    list: bool,
+    discover: bool,
 }
 // The implementation of the program arguments just provides a method
 // to initialize one with the appropriate defaults.
@@ -25,94 +101,1049 @@ impl ProgramArguments {
     ///
     fn new() -> ProgramArguments {
         ProgramArguments {
-            directory: String::from("/dev/shm"),
-            ring_name: String::from(""), // no default
+            directory: path::PathBuf::from("/dev/shm"),
+            ring_names: Vec::new(), // no default
+            host: None,             // Local ring.
             portman: 30000,
+            portman_host: None,
+            portman_retries: 0,
             comment: String::from(""),
+            name_suffix: None,
+            options: drain::Options::new(),
+            output: None, // stdout.
+            unix_socket: None,
+            unix_listen: false,
+            append: false,
+            tee: Vec::new(),
+            sync_interval: None,
+            compress: Compression::None,
+            connect: None,
+            reconnect: false,
+            with_header: false,
+            pidfile: None,
+            watchdog: None,
+            require_producer: false,
+            stop_on_stdin_eof: false,
+            retries: 0,
+            print_slot: false,
+            ringmaster_timeout: Duration::from_secs(5),
+            ringmaster_index: None,
+            ringmaster_service: String::from(ringmaster_client::RINGMASTER_SERVICE),
+            ringmaster_socket: None,
+            slot: None,
+            keepalive: None,
+            dry_run: false,
+            allow_tty: false,
+            canonicalize: true,
+            list: false,
+            discover: false,
         }
     }
 }
 fn main() {
     let args = process_args();
-    eprintln!("{:#?}", args);
+    debug!("{:#?}", args);
+
+    // Everything that can go wrong comes back as a Failure which knows
+    // our exit status.  run has returned so the clients have been dropped
+    // (releasing our consumer slots and ringmaster connections)
+    // before we exit:
+
+    let status = match run(&args) {
+        Ok(()) => EXIT_OK,
+        Err(failure) => {
+            error!("{}", failure.message);
+            failure.status
+        }
+    };
+    process::exit(status);
+}
+// Why we're exiting unsuccessfully: a message saying what we were
+// doing when it went wrong and the exit status that tells whoever
+// started us what kind of failure it was.
+//
+#[derive(Debug)]
+struct Failure {
+    status: i32,
+    message: String,
+}
+impl Failure {
+    fn new(status: i32, message: String) -> Failure {
+        Failure { status, message }
+    }
+}
+// Do what the arguments ask:
+//
+fn run(args: &ProgramArguments) -> Result<(), Failure> {
+    // --list just describes the rings in the directory:
+
+    if args.list {
+        return list_rings(&args.directory);
+    }
+    // --discover asks the ringmaster which rings it knows instead:
+
+    if args.discover {
+        configure_client(args);
+        return discover_rings();
+    }
+
+    // SIGINT and SIGTERM just set a flag that drain (and the waits
+    // before it: for a --unix-listen reader, between --retry attempts)
+    // checks so that we can shut down cleanly rather than just die.  The
+    // handlers restart interrupted system calls, so a second signal, e.g.
+    // when a write to a stuck output never returns, exits right away
+    // with the usual 128 + signal status:
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in &[signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        signal_hook::flag::register_conditional_shutdown(
+            *signal,
+            128 + *signal,
+            Arc::clone(&shutdown),
+        )
+        .and_then(|_| signal_hook::flag::register(*signal, Arc::clone(&shutdown)))
+        .map_err(|e| {
+            Failure::new(
+                EXIT_USAGE,
+                format!("Unable to establish signal handler: {}", e),
+            )
+        })?;
+    }
+    if args.stop_on_stdin_eof {
+        watch_stdin(&shutdown);
+    }
+
+    // Open the output before attaching so a bad output path fails before
+    // we become a consumer.  A dry run doesn't write data so it
+    // leaves the output alone (creating it would truncate it):
+
+    let mut target: Box<dyn Write + Send> = if args.dry_run {
+        Box::new(io::sink())
+    } else {
+        open_output(args, &shutdown)?
+    };
+    if !args.tee.is_empty() && !args.dry_run {
+        target = Box::new(open_tees(args, target)?);
+    }
+    if let Some(limit) = args.watchdog {
+        target = watch_output(target, limit);
+    }
 
     // The next step in the game is to establish ourselves as a consumer of
-    // the specified ring.  To do that we need to construct the full ringbuffer
-    // path:
+    // the specified ring(s).  If one fails, returning drops those we
+    // did attach, releasing them.
 
-    let mut path_buf = path::PathBuf::from(args.directory);
-    path_buf.push(args.ring_name);
+    configure_client(args);
+    let mut clients = Vec::<ringmaster_client::RingClient>::new();
+    for ring in &args.ring_names {
+        let consumer_info = attach(args, ring, &shutdown).map_err(|e| {
+            Failure::new(
+                EXIT_ATTACH_FAILED,
+                format!(
+                    "Failed to attach {}: {}",
+                    describe_ring(args, &ring.to_string_lossy()),
+                    e
+                ),
+            )
+        })?;
+        if let ringmaster_client::ClientType::Producer(_p) = &consumer_info.client {
+            // This is a bad bug we're supposed to be a consumer:
 
-    match ringmaster_client::attach_consumer(path_buf.to_str().expect("BUG")) {
-        Err(e) => {
-            eprintln!("Failed to attach ring buffer : {}", e);
-            process::exit(-1);
+            return Err(Failure::new(
+                EXIT_ATTACH_FAILED,
+                format!(
+                    "Attaching {} returned a producer not a consumer",
+                    describe_ring(args, &ring.to_string_lossy())
+                ),
+            ));
         }
-        Ok(consumer_info) => {
-            match consumer_info.client {
-                ringmaster_client::ClientType::Consumer(mut c) => {
-                    // If a comment has been given update the process title:
+        if args.print_slot {
+            match consumer_info.client.slot() {
+                Some(slot) => eprintln!("slot={}", slot),
+                None => eprintln!("slot=remote"),
+            }
+        }
+        clients.push(consumer_info);
+    }
+    // A dry run only checks that we can attach; we have so we're done:
+
+    if args.dry_run {
+        info!(
+            "Dry run: attached to {} ring(s) successfully",
+            clients.len()
+        );
+        return Ok(());
+    }
+    // If a comment or name suffix has been given update the process title:
 
-                    if args.comment != "" {
-                        let title = format!("ring2stdout - {}", args.comment);
-                        proctitle::set_title(title);
-                    }
+    let name = match &args.name_suffix {
+        Some(suffix) => format!("ring2stdout-{}", suffix),
+        None => String::from("ring2stdout"),
+    };
+    if !args.comment.is_empty() {
+        set_process_title(&format!("{} - {}", name, args.comment));
+    } else if args.name_suffix.is_some() {
+        set_process_title(&name);
+    }
+    // Now that we're attached tell any supervisor who we are.  The file
+    // goes when _pidfile is dropped, however we leave run:
 
-                    output_data(&mut c);
-                }
-                ringmaster_client::ClientType::Producer(_p) => {
-                    // This is a bad bug we're supposed to be a consumer:
+    let _pidfile = match &args.pidfile {
+        Some(path) => Some(PidFile::create(path).map_err(|e| {
+            Failure::new(
+                EXIT_USAGE,
+                format!("Unable to write the pid file {}: {}", path, e),
+            )
+        })?),
+        None => None,
+    };
+
+    // With --compress everything, header and all, goes through the encoder:
+
+    let mut target = Output::new(args.compress, target).map_err(|e| {
+        Failure::new(
+            EXIT_OUTPUT_ERROR,
+            format!("Unable to start compressing the output: {}", e),
+        )
+    })?;
 
-                    eprintln!("ERROR - a producer was returned not a consumer");
-                    process::exit(-1);
+    // With --with-header the stream starts by describing itself:
+
+    if args.with_header {
+        let header = framing::StreamHeader::new(&ring_list(args, ","));
+        if let Err(e) = framing::write_stream_header(&mut target, &header) {
+            return stop_result(args, output_failure(e));
+        }
+    }
+
+    let (reason, stats) = if args.options.queue_depth.is_some() {
+        drain::drain_queued(&mut clients, &mut target, &args.options, &shutdown)
+    } else {
+        drain::drain(&mut clients, &mut target, &args.options, &shutdown)
+    };
+    // Unless the output failed, end it properly (for compressed output
+    // that writes the end of the compressed stream):
+
+    let reason = match reason {
+        drain::StopReason::OutputClosed | drain::StopReason::OutputError(_) => reason,
+        _ => match target.finish() {
+            Ok(()) => reason,
+            Err(e) => output_failure(e),
+        },
+    };
+
+    if args.options.max_items.is_some() {
+        info!("Forwarded {} ring items", stats.items);
+    }
+    // On a clean exit, however we got there, summarize the run and report
+    // the checksum if asked for:
+
+    if !matches!(
+        reason,
+        drain::StopReason::RingError(_)
+            | drain::StopReason::OutputError(_)
+            | drain::StopReason::NoData
+    ) {
+        let elapsed = stats.elapsed().as_secs_f64();
+        info!(
+            "summary: {} bytes, {} gets, {} timeouts in {:.3}s ({:.3} MB/s), {}",
+            stats.bytes,
+            stats.gets,
+            stats.timeouts,
+            elapsed,
+            stats.bytes as f64 / elapsed / 1.0e6,
+            stop_description(&reason)
+        );
+        if let Some(checksum) = &stats.checksum {
+            eprintln!("bytes={} crc32=0x{:08x}", checksum.bytes, checksum.crc32());
+        }
+        if let Some(histogram) = &stats.histogram {
+            eprint!("{}", histogram);
+        }
+    }
+    stop_result(args, reason)
+}
+// How a failed write to the output stops us: the reader going away
+// is a normal end, anything else is an error.
+//
+fn output_failure(e: io::Error) -> drain::StopReason {
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        drain::StopReason::OutputClosed
+    } else {
+        drain::StopReason::OutputError(e)
+    }
+}
+// The output as drain writes it: as opened or, with --compress, through
+// an encoder.  finish must be called at the end to complete the
+// compressed stream; dropping a compressed Output leaves it incomplete.
+// drain flushes whenever the rings are idle and each encoder flush
+// writes a little, so encoders are only flushed if they've had data
+// since the last one.
+//
+struct Output {
+    encoder: Encoder,
+    unflushed: bool,
+}
+enum Encoder {
+    Plain(Box<dyn Write + Send>),
+    Gzip(flate2::write::GzEncoder<Box<dyn Write + Send>>),
+    Zstd(zstd::stream::write::Encoder<'static, Box<dyn Write + Send>>),
+}
+impl Output {
+    fn new(compression: Compression, out: Box<dyn Write + Send>) -> io::Result<Output> {
+        let encoder = match compression {
+            Compression::None => Encoder::Plain(out),
+            Compression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                out,
+                flate2::Compression::default(),
+            )),
+            Compression::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(out, 0)?),
+        };
+        Ok(Output {
+            encoder,
+            unflushed: false,
+        })
+    }
+    fn finish(self) -> io::Result<()> {
+        match self.encoder {
+            Encoder::Plain(mut out) => out.flush(),
+            Encoder::Gzip(encoder) => encoder.finish()?.flush(),
+            Encoder::Zstd(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.unflushed = true;
+        match &mut self.encoder {
+            Encoder::Plain(out) => out.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        let unflushed = std::mem::replace(&mut self.unflushed, false);
+        match &mut self.encoder {
+            Encoder::Plain(out) => out.flush(),
+            Encoder::Gzip(encoder) if unflushed => encoder.flush(),
+            Encoder::Zstd(encoder) if unflushed => encoder.flush(),
+            _ => Ok(()),
+        }
+    }
+}
+// --stop-on-stdin-eof: a thread reads (and throws away) stdin and when
+// it's closed sets shutdown so we stop cleanly just as for a signal.
+// Failing to read stdin is treated the same way.  The thread is left
+// blocked in its read when we exit for any other reason.
+//
+fn watch_stdin(shutdown: &Arc<AtomicBool>) {
+    let shutdown = Arc::clone(shutdown);
+    thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        let mut buffer = [0_u8; 4096];
+        loop {
+            match stdin.read(&mut buffer) {
+                Ok(0) => {
+                    info!("stdin closed; shutting down");
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    warn!("Unable to read stdin ({}); shutting down", e);
+                    break;
+                }
+            }
+        }
+        shutdown.store(true, Ordering::Relaxed);
+    });
+}
+// What we call a ring in messages; where it is matters as much as
+// its name:
+//
+fn describe_ring(args: &ProgramArguments, ring: &str) -> String {
+    match &args.host {
+        Some(host) => format!("ring buffer {} in host {}", ring, host),
+        None => format!("ring buffer {} in {}", ring, args.directory.display()),
+    }
+}
+// The ring names joined with separator.  Names that aren't UTF-8 are
+// shown as well as we can.
+//
+fn ring_list(args: &ProgramArguments, separator: &str) -> String {
+    let names: Vec<_> = args
+        .ring_names
+        .iter()
+        .map(|ring| ring.to_string_lossy())
+        .collect();
+    names.join(separator)
+}
+// Pass the port manager and ringmaster settings on to ringmaster_client:
+//
+fn configure_client(args: &ProgramArguments) {
+    ringmaster_client::set_portman_port(args.portman);
+    if let Some(host) = &args.portman_host {
+        ringmaster_client::set_portman_host(host);
+    }
+    ringmaster_client::set_portman_retries(args.portman_retries);
+    ringmaster_client::set_ringmaster_timeout(args.ringmaster_timeout);
+    ringmaster_client::set_ringmaster_index(args.ringmaster_index);
+    ringmaster_client::set_ringmaster_service(&args.ringmaster_service);
+    ringmaster_client::set_ringmaster_socket(args.ringmaster_socket.clone());
+    ringmaster_client::set_consumer_slot(args.slot);
+    ringmaster_client::set_ringmaster_keepalive(args.keepalive);
+}
+// Print a table of the rings the ringmaster knows to stderr:
+//
+fn discover_rings() -> Result<(), Failure> {
+    let rings = ringmaster_client::list_ringmaster_rings().map_err(|e| {
+        Failure::new(
+            EXIT_RING_ERROR,
+            format!("Unable to get the ringmaster's rings: {}", e),
+        )
+    })?;
+    eprintln!(
+        "{:<24} {:>12} {:>10} {:>10}",
+        "NAME", "SIZE", "PRODUCER", "CONSUMERS"
+    );
+    for ring in &rings {
+        let size = match ring.data_bytes {
+            Some(bytes) => bytes.to_string(),
+            None => String::from("?"),
+        };
+        let producer = match ring.producer {
+            Some(pid) => pid.to_string(),
+            None => String::from("-"),
+        };
+        eprintln!(
+            "{:<24} {:>12} {:>10} {:>10}",
+            ring.name, size, producer, ring.consumers
+        );
+    }
+    eprintln!("{} ring(s) known to the ringmaster", rings.len());
+    Ok(())
+}
+// Print a table of the ring buffers in directory to stderr:
+//
+fn list_rings(directory: &path::Path) -> Result<(), Failure> {
+    let (rings, skipped) = ringmaster_client::list_rings(directory).map_err(|e| {
+        Failure::new(
+            EXIT_USAGE,
+            format!("Unable to list {}: {}", directory.display(), e),
+        )
+    })?;
+    eprintln!(
+        "{:<24} {:>12} {:>10} {:>10}",
+        "NAME", "SIZE", "PRODUCER", "CONSUMERS"
+    );
+    for ring in &rings {
+        let producer = match ring.producer {
+            Some(pid) => pid.to_string(),
+            None => String::from("-"),
+        };
+        eprintln!(
+            "{:<24} {:>12} {:>10} {:>10}",
+            ring.name,
+            ring.data_bytes,
+            producer,
+            format!("{}/{}", ring.consumers, ring.max_consumers)
+        );
+    }
+    eprintln!(
+        "{} ring buffer(s), {} other file(s) skipped",
+        rings.len(),
+        skipped
+    );
+    Ok(())
+}
+// Attach to a ring as a consumer.  Failures that may only mean the
+// ring, ringmaster, port manager or (with --require-producer) producer
+// aren't there yet (MapError, NoRingMaster, NoPortManager, NoProducer and
+// the ringmaster not knowing the ring, RingMasterNoRing) are retried up to
+// --retry times with exponential backoff.  Anything else, e.g. no free
+// consumer slots or the ringmaster refusing us, fails right away.  So
+// does being shut down while waiting to try again.
+//
+fn attach(
+    args: &ProgramArguments,
+    ring: &OsStr,
+    shutdown: &AtomicBool,
+) -> ringmaster_client::RingClientResult {
+    let max_delay = Duration::from_secs(10);
+    let mut delay = Duration::from_millis(100);
+    let mut attempts = 0;
+    loop {
+        let result = attach_once(args, ring);
+        match &result {
+            Err(e @ ringmaster_client::Error::MapError(_))
+            | Err(e @ ringmaster_client::Error::NoRingMaster)
+            | Err(e @ ringmaster_client::Error::NoPortManager(_))
+            | Err(e @ ringmaster_client::Error::NoProducer)
+            | Err(e @ ringmaster_client::Error::RingMasterNoRing(_))
+                if attempts < args.retries =>
+            {
+                attempts += 1;
+                warn!(
+                    "Attach attempt {} failed: {} - retrying in {:?}",
+                    attempts, e, delay
+                );
+                if sleep_unless_shutdown(delay, shutdown) {
+                    info!("Shut down while waiting to attach again");
+                    return result;
+                }
+                delay = std::cmp::min(delay * 2, max_delay);
+            }
+            _ => return result,
+        }
+    }
+}
+// Sleep for delay, a bit at a time so that we notice being shut down.
+// Returns whether we were.
+//
+fn sleep_unless_shutdown(delay: Duration, shutdown: &AtomicBool) -> bool {
+    let step = Duration::from_millis(50);
+    let end = Instant::now() + delay;
+    loop {
+        if shutdown.load(Ordering::Relaxed) {
+            return true;
+        }
+        let now = Instant::now();
+        if now >= end {
+            return false;
+        }
+        thread::sleep(std::cmp::min(step, end - now));
+    }
+}
+// Make a single attempt to attach.  With --require-producer, a local
+// ring without a producer is a NoProducer error (and we let go of it);
+// we can't see whether remote rings have one.
+//
+fn attach_once(args: &ProgramArguments, ring: &OsStr) -> ringmaster_client::RingClientResult {
+    let client = attach_client(args, ring)?;
+    if args.require_producer {
+        match client.producer_attached() {
+            Some(false) => return Err(ringmaster_client::Error::NoProducer),
+            None => warn!(
+                "Can't tell if the remote ring {} has a producer; --require-producer ignored",
+                ring.to_string_lossy()
+            ),
+            Some(true) => {}
+        }
+    }
+    Ok(client)
+}
+// Attach to the ring.  For a local ring we need to construct the full
+// ringbuffer path, for a remote ring the remote ringmaster just needs
+// the ring name.  Unless --no-canonicalize was given, symbolic links in
+// the path are resolved so that the file we map and the ring name we give
+// the ringmaster are the ring's real ones.  Remote ring names go in a
+// text request so they must be UTF-8.
+//
+fn attach_client(args: &ProgramArguments, ring: &OsStr) -> ringmaster_client::RingClientResult {
+    if let Some(host) = &args.host {
+        match ring.to_str() {
+            Some(ring) => ringmaster_client::attach_remote_consumer(host, ring),
+            None => Err(ringmaster_client::Error::MapError(format!(
+                "{} is not a valid UTF-8 ring name",
+                ring.to_string_lossy()
+            ))),
+        }
+    } else {
+        let mut path_buf = args.directory.join(ring);
+        if args.canonicalize {
+            path_buf = canonical_ring_path(&path_buf)?;
+        }
+        ringmaster_client::attach_consumer(path_buf)
+    }
+}
+// Resolve the symbolic links in a ring buffer path.  If that fails it's
+// a MapError, like failing to map it, so --retry applies; a link to a
+// ring that doesn't exist (yet) gets its own message.
+//
+fn canonical_ring_path(ring_path: &path::Path) -> Result<path::PathBuf, ringmaster_client::Error> {
+    fs::canonicalize(ring_path).map_err(|e| {
+        let dangling = e.kind() == io::ErrorKind::NotFound
+            && fs::symlink_metadata(ring_path).is_ok_and(|m| m.file_type().is_symlink());
+        ringmaster_client::Error::MapError(if dangling {
+            format!(
+                "{} is a symbolic link to a file that does not exist",
+                ring_path.display()
+            )
+        } else {
+            format!("Unable to resolve {}: {}", ring_path.display(), e)
+        })
+    })
+}
+// Open where the data goes; the --output file or FIFO if given,
+// the --unix-socket or --connect address if given, otherwise stdout.
+// Failing to open the output is fatal.  So is stdout being a terminal,
+// which binary data would make a mess of, unless --allow-tty says it's
+// meant.
+//
+fn open_output(
+    args: &ProgramArguments,
+    shutdown: &Arc<AtomicBool>,
+) -> Result<Box<dyn Write + Send>, Failure> {
+    if let Some(address) = &args.connect {
+        match connect_output(address) {
+            Ok(stream) => Ok(Box::new(TcpOutput {
+                address: address.clone(),
+                stream,
+                reconnect: args.reconnect,
+                shutdown: Arc::clone(shutdown),
+            })),
+            Err(e) => Err(Failure::new(
+                EXIT_USAGE,
+                format!("Unable to connect to {} for output: {}", address, e),
+            )),
+        }
+    } else if let Some(path) = &args.unix_socket {
+        match open_unix_socket(path, args.unix_listen, shutdown) {
+            Ok(s) => Ok(Box::new(s)),
+            Err(e) => Err(Failure::new(
+                EXIT_USAGE,
+                format!("Unable to use the Unix socket {} for output: {}", path, e),
+            )),
+        }
+    } else if let Some(path) = &args.output {
+        let opened = if args.append {
+            fs::OpenOptions::new().append(true).create(true).open(path)
+        } else {
+            fs::File::create(path)
+        };
+        match opened {
+            Ok(f) => {
+                if let Some(interval) = args.sync_interval {
+                    sync_periodically(path, &f, interval, shutdown);
                 }
+                Ok(Box::new(f))
             }
+            Err(e) => Err(Failure::new(
+                EXIT_USAGE,
+                format!("Unable to open {} for output: {}", path, e),
+            )),
+        }
+    } else if io::stdout().is_terminal() && !args.allow_tty {
+        Err(Failure::new(
+            EXIT_USAGE,
+            String::from(
+                "stdout is a terminal and the ring data is binary; redirect stdout, use --output or, to see it anyway, --allow-tty",
+            ),
+        ))
+    } else {
+        Ok(Box::new(io::stdout()))
+    }
+}
+// --sync-interval: a thread with its own handle on the output file
+// syncs the file's data to disk every interval so a crash loses at most
+// about that much (plus what drain has buffered).  Doing it here keeps
+// the sync's stall out of the drain loop.  Only regular files can be
+// synced; for anything else (e.g. a FIFO) there's nothing to do.
+//
+fn sync_periodically(path: &str, file: &fs::File, interval: Duration, shutdown: &Arc<AtomicBool>) {
+    if !file.metadata().is_ok_and(|m| m.is_file()) {
+        debug!("{} is not a regular file; --sync-interval ignored", path);
+        return;
+    }
+    let file = match file.try_clone() {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Unable to sync {}: {}", path, e);
+            return;
         }
     };
-    process::exit(-1); // all exits are bad news:
-}
-//
-// Main loop of the program.
-// Each get, we try to do in MByte chunks which we then
-// send to stdout.  We use timed_get with a timeout of a 1ms to reduce
-// latency.
-//
-fn output_data(ring: &mut nscldaq_ringbuffer::ringbuffer::consumer::Consumer) {
-    // We must use a vec -- or a static buffer else the buffer will
-    // overflow the stack.  Vec will allocate on the heap,
-    // Note that evidently, the vector & can be treated as &[u8] which is
-    // what both timed_get and write_all need.
-    let mut data = Vec::<u8>::new();
-    data.reserve(1024 * 1024);
-    data.resize(1024 * 1024, 0);
+    let path = String::from(path);
+    let shutdown = Arc::clone(shutdown);
+    thread::spawn(move || {
+        while !shutdown.load(Ordering::Relaxed) {
+            thread::sleep(interval);
+            if let Err(e) = file.sync_data() {
+                warn!("Unable to sync {}, no longer syncing it: {}", path, e);
+                break;
+            }
+        }
+    });
+}
+// Connect to the Unix domain socket at path or, if listen, create it,
+// wait for one reader to connect and remove it again so nobody else
+// can connect (and it isn't left behind).  We wait by polling so that
+// being shut down while waiting also removes it.
+//
+#[cfg(unix)]
+fn open_unix_socket(path: &str, listen: bool, shutdown: &AtomicBool) -> io::Result<UnixStream> {
+    if listen {
+        let listener = UnixListener::bind(path)?;
+        info!("Waiting for a connection on {}", path);
+        let result = accept_unless_shutdown(&listener, shutdown);
+        let _ = fs::remove_file(path);
+        result
+    } else {
+        UnixStream::connect(path)
+    }
+}
+#[cfg(unix)]
+fn accept_unless_shutdown(
+    listener: &UnixListener,
+    shutdown: &AtomicBool,
+) -> io::Result<UnixStream> {
+    listener.set_nonblocking(true)?;
     loop {
-        match ring.timed_get(&mut data, Duration::from_millis(1)) {
-            Ok(n) => {
-                // Actually read n bytes.  We need to send them as binary
-                // to stdout.
-
-                io::stdout()
-                    .write_all(&data[0..n])
-                    .expect("Failed to write to stdout");
-                io::stdout().flush().expect("Failed to flush output");
-            }
-            Err(e) => {
-                // Time out is ok but anything else is fatal:
-
-                match e {
-                    nscldaq_ringbuffer::ringbuffer::consumer::Error::Timeout => {}
-                    _ => {
-                        eprintln!(
-                            "Error reading from ring buffer: {}",
-                            nscldaq_ringbuffer::ringbuffer::consumer::error_string(&e)
-                        );
-                        break;
-                    }
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if shutdown.load(Ordering::Relaxed) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Interrupted,
+                        "shut down while waiting for a connection",
+                    ));
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+#[cfg(not(unix))]
+fn open_unix_socket(_path: &str, _listen: bool, _shutdown: &AtomicBool) -> io::Result<fs::File> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix domain sockets are not supported on this system",
+    ))
+}
+// The --connect output: a TCP connection to address (host:port).
+// With reconnect, when the connection is lost we connect again (every
+// half second until that works or we're shut down) and carry on
+// writing.  Whatever was in flight when the connection went is lost.
+// Otherwise losing the connection ends the output like a closed pipe.
+//
+struct TcpOutput {
+    address: String,
+    stream: TcpStream,
+    reconnect: bool,
+    shutdown: Arc<AtomicBool>,
+}
+impl TcpOutput {
+    // Reconnect after a write failed with e if we should; else
+    // give back e:
+    //
+    fn recover(&mut self, e: io::Error) -> io::Result<()> {
+        let lost = matches!(
+            e.kind(),
+            io::ErrorKind::BrokenPipe
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+        );
+        if !(self.reconnect && lost) {
+            return Err(e);
+        }
+        warn!(
+            "Lost the connection to {}: {}; reconnecting",
+            self.address, e
+        );
+        loop {
+            if self.shutdown.load(Ordering::Relaxed) {
+                return Err(e);
+            }
+            match connect_output(&self.address) {
+                Ok(stream) => {
+                    self.stream = stream;
+                    warn!(
+                        "Reconnected to {}; the data is not contiguous across this point",
+                        self.address
+                    );
+                    return Ok(());
+                }
+                Err(e) => {
+                    debug!("Reconnecting to {} failed: {}", self.address, e);
+                    thread::sleep(Duration::from_millis(500));
+                }
+            }
+        }
+    }
+}
+impl Write for TcpOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            match self.stream.write(buf) {
+                Err(e) if e.kind() != io::ErrorKind::Interrupted => self.recover(e)?,
+                result => return result,
+            }
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        loop {
+            match self.stream.flush() {
+                Err(e) => self.recover(e)?,
+                result => return result,
+            }
+        }
+    }
+}
+// --tee: a copy of everything written to the output goes to each of the
+// tee files as well.  The output is what matters, so a tee that fails is
+// logged and dropped while the output carries on; only the output's
+// errors are returned.  The copies are of what the output accepted,
+// which can be less than we were asked to write.
+//
+struct MultiWriter {
+    primary: Box<dyn Write + Send>,
+    copies: Vec<(String, Box<dyn Write + Send>)>, // (name, target)
+}
+impl MultiWriter {
+    fn new(primary: Box<dyn Write + Send>) -> MultiWriter {
+        MultiWriter {
+            primary,
+            copies: Vec::new(),
+        }
+    }
+    fn add_copy(&mut self, name: &str, copy: Box<dyn Write + Send>) {
+        self.copies.push((String::from(name), copy));
+    }
+    // Do operation to every copy, dropping those that fail:
+    //
+    fn on_copies<F>(&mut self, what: &str, mut operation: F)
+    where
+        F: FnMut(&mut Box<dyn Write + Send>) -> io::Result<()>,
+    {
+        self.copies
+            .retain_mut(|(name, copy)| match operation(copy) {
+                Ok(()) => true,
+                Err(e) => {
+                    error!(
+                        "Unable to {} the tee file {}, no longer writing it: {}",
+                        what, name, e
+                    );
+                    false
                 }
+            });
+    }
+}
+impl Write for MultiWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.primary.write(buf)?;
+        self.on_copies("write", |copy| copy.write_all(&buf[0..n]));
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.primary.flush()?;
+        self.on_copies("flush", |copy| copy.flush());
+        Ok(())
+    }
+}
+// Open (create or truncate) the --tee files and combine them with the
+// output:
+//
+fn open_tees(
+    args: &ProgramArguments,
+    output: Box<dyn Write + Send>,
+) -> Result<MultiWriter, Failure> {
+    let mut writer = MultiWriter::new(output);
+    for path in &args.tee {
+        let file = fs::File::create(path).map_err(|e| {
+            Failure::new(
+                EXIT_USAGE,
+                format!("Unable to open the tee file {}: {}", path, e),
+            )
+        })?;
+        writer.add_copy(path, Box::new(file));
+    }
+    Ok(writer)
+}
+// --watchdog: the output is wrapped so that a watchdog thread can see
+// how long the current write (or flush) has been blocked.  An idle ring
+// means we're not writing at all; a write that blocks for the whole
+// watchdog time means downstream is stuck.  We can't unblock it, so
+// the watchdog logs an error and exits.
+//
+struct WatchedOutput {
+    inner: Box<dyn Write + Send>,
+    epoch: Instant,
+    blocked_since: Arc<AtomicU64>, // ms after epoch + 1, 0 when not writing.
+}
+impl WatchedOutput {
+    fn watch<T>(&mut self, op: impl FnOnce(&mut Box<dyn Write + Send>) -> T) -> T {
+        let now = self.epoch.elapsed().as_millis() as u64 + 1;
+        self.blocked_since.store(now, Ordering::Relaxed);
+        let result = op(&mut self.inner);
+        self.blocked_since.store(0, Ordering::Relaxed);
+        result
+    }
+}
+impl Write for WatchedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.watch(|out| out.write(buf))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.watch(|out| out.flush())
+    }
+}
+// Wrap the output and start its watchdog thread.  The consumer slots
+// are not released when the watchdog exits; the ringmaster frees them
+// when our connections to it close.
+//
+fn watch_output(out: Box<dyn Write + Send>, limit: Duration) -> Box<dyn Write + Send> {
+    let watched = WatchedOutput {
+        inner: out,
+        epoch: Instant::now(),
+        blocked_since: Arc::new(AtomicU64::new(0)),
+    };
+    let (epoch, blocked_since) = (watched.epoch, Arc::clone(&watched.blocked_since));
+    let check = std::cmp::min(limit / 4, Duration::from_secs(1));
+    thread::spawn(move || loop {
+        thread::sleep(check);
+        let since = blocked_since.load(Ordering::Relaxed);
+        if since != 0 {
+            let blocked = epoch
+                .elapsed()
+                .saturating_sub(Duration::from_millis(since - 1));
+            if blocked >= limit {
+                error!(
+                    "Watchdog: writing the output has been blocked for {}s; downstream is stuck, exiting",
+                    blocked.as_secs()
+                );
+                process::exit(EXIT_OUTPUT_ERROR);
+            }
+        }
+    });
+    Box::new(watched)
+}
+// Connect to the --connect address.  We don't batch small writes
+// (Nagle) as low latency matters more:
+//
+fn connect_output(address: &str) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+// What we call the output in messages:
+//
+fn output_name(args: &ProgramArguments) -> &str {
+    args.connect
+        .as_deref()
+        .or(args.unix_socket.as_deref())
+        .or(args.output.as_deref())
+        .unwrap_or("stdout")
+}
+// --pidfile: our process id is written to the file when it's created
+// and the file is removed when this is dropped.  The watchdog's exit
+// skips that, leaving the file behind for the supervisor to notice.
+//
+struct PidFile {
+    path: String,
+}
+impl PidFile {
+    fn create(path: &str) -> io::Result<PidFile> {
+        fs::write(path, format!("{}\n", process::id()))?;
+        debug!("Wrote our pid to {}", path);
+        Ok(PidFile {
+            path: String::from(path),
+        })
+    }
+}
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Unable to remove the pid file {}: {}", self.path, e);
+        }
+    }
+}
+// Set the title that system process displays show for us.
+// The proctitle crate sets the process name, on Linux via
+// prctl(PR_SET_NAME).  Linux limits that name to 15 bytes which is
+// what top and ps -o comm show, so that's not enough to see
+// much of a comment.  On Linux we therefore also overwrite our
+// argv area (located via /proc/self/stat) which is what ps shows as our
+// command line.  The title is truncated to the length of the original
+// command line.  On other systems only proctitle is used.
+//
+fn set_process_title(title: &str) {
+    proctitle::set_title(title);
+
+    #[cfg(target_os = "linux")]
+    {
+        if let Some((start, end)) = argv_area() {
+            let room = end - start - 1; // Leave the final nul.
+            let n = std::cmp::min(room, title.len());
+            let area = start as *mut u8;
+
+            // Safety: the kernel tells us [start, end) is our argv
+            // strings which live in our own writable stack.  We've already
+            // parsed the arguments so nothing needs their old contents.
+
+            unsafe {
+                std::ptr::write_bytes(area, 0, end - start);
+                std::ptr::copy_nonoverlapping(title.as_ptr(), area, n);
             }
         }
     }
 }
+// Return the start and end addresses of the argv strings from
+// /proc/self/stat (fields 48 and 49).  The command field can contain
+// spaces so count fields from the closing parenthesis that ends it.
+//
+#[cfg(target_os = "linux")]
+fn argv_area() -> Option<(usize, usize)> {
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    let after_command = &stat[stat.rfind(')')? + 1..];
+    let fields: Vec<&str> = after_command.split_whitespace().collect();
+    let start = fields.get(48 - 3)?.parse::<usize>().ok()?;
+    let end = fields.get(49 - 3)?.parse::<usize>().ok()?;
+    if end > start {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+// Why we stopped, for the summary:
+//
+fn stop_description(reason: &drain::StopReason) -> &'static str {
+    match reason {
+        drain::StopReason::EndOfData => "end of data",
+        drain::StopReason::LimitReached => "limit reached",
+        drain::StopReason::Shutdown => "shut down by signal or stdin closing",
+        drain::StopReason::OutputClosed => "output closed",
+        drain::StopReason::NoData => "no data",
+        drain::StopReason::RingError(_) => "ring error",
+        drain::StopReason::OutputError(_) => "output error",
+    }
+}
+// Map the reason drain stopped into success or a Failure with the
+// matching exit status.  The downstream reader going away
+// (OutputClosed) is a normal end:
+//
+fn stop_result(args: &ProgramArguments, reason: drain::StopReason) -> Result<(), Failure> {
+    match reason {
+        drain::StopReason::EndOfData
+        | drain::StopReason::LimitReached
+        | drain::StopReason::Shutdown
+        | drain::StopReason::OutputClosed => Ok(()),
+        drain::StopReason::RingError(e) => Err(Failure::new(
+            EXIT_RING_ERROR,
+            format!(
+                "Error reading from {}: {}",
+                describe_ring(args, &ring_list(args, ", ")),
+                e
+            ),
+        )),
+        drain::StopReason::OutputError(e) => Err(Failure::new(
+            EXIT_OUTPUT_ERROR,
+            format!("Error writing output to {}: {}", output_name(args), e),
+        )),
+        drain::StopReason::NoData => Err(Failure::new(
+            EXIT_NO_DATA,
+            format!(
+                "No data arrived from {} within {}s of attaching",
+                describe_ring(args, &ring_list(args, ", ")),
+                args.options
+                    .first_data_timeout
+                    .map_or(0, |limit| limit.as_secs())
+            ),
+        )),
+    }
+}
 // Define and process the arguments using clap (old since we need an older
 // rust edition than current:
 
@@ -123,7 +1154,7 @@ fn process_args() -> ProgramArguments {
     // then run a parse on the argv:
 
     let parser = App::new("ring2stdout") // CARGO_BIN_NAME not in old versions.
-        .version(env!("CARGO_PKG_VERSION"))
+        .version(VERSION)
         .author("Ron Fox")
         .about(env!("CARGO_PKG_DESCRIPTION"))
         .arg(
@@ -131,18 +1162,41 @@ fn process_args() -> ProgramArguments {
                 .short("d")
                 .long("directory")
                 .value_name("DIRECTORY")
-                .help("Directory of ring buffer shared memory files managed by the ringmaster")
-                .takes_value(true)
-                .default_value("/dev/shm"),
+                .help("Directory of ring buffer shared memory files managed by the ringmaster (default: $RINGTOSTDOUT_DIR, else /dev/shm)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mkdir")
+                .long("mkdir")
+                .help("Create the ring buffer directory (and its parents) if it does not exist"),
+        )
+        .arg(
+            Arg::with_name("no_directory_check")
+                .long("no-directory-check")
+                .help("Don't check the ring buffer directory can be listed; just map the ring"),
+        )
+        .arg(
+            Arg::with_name("no_canonicalize")
+                .long("no-canonicalize")
+                .help("Use the ring buffer path as given, without resolving symbolic links"),
         )
         .arg(
             Arg::with_name("ring_name")
                 .short("r")
                 .long("ring")
                 .value_name("RINGBUFFER")
-                .help("Name of the ring buffer we should take data from")
+                .help("Name of a ring buffer we should take data from; may be repeated (default: $RINGTOSTDOUT_RING)")
                 .takes_value(true)
-                .required(true),
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("host")
+                .short("H")
+                .long("host")
+                .value_name("HOST")
+                .help("Host of a remote ring; if omitted the ring is local")
+                .takes_value(true),
         )
         .arg(
             Arg::with_name("portman")
@@ -154,52 +1208,1099 @@ fn process_args() -> ProgramArguments {
                 .default_value("30000"),
         )
         .arg(
-            Arg::with_name("comment")
-                .short("c")
-                .long("comment")
-                .value_name("COMMENT")
+            Arg::with_name("portman_host")
+                .long("portman-host")
+                .value_name("HOST")
+                .help("Host whose port manager we ask for the ringmaster port")
                 .takes_value(true),
         )
-        .get_matches();
-
-    // override default directory - the diretory must exist:
-
-    if let Some(directory) = parser.value_of("directory") {
-        if fs::read_dir(directory).is_err() {
-            eprintln!("{} Must be a readable directory", directory);
-            process::exit(-1);
-        } else {
-            result.directory = String::from(directory);
-        }
-    }
-    // ring name must be present else the program can't run:
-
-    if let Some(ring) = parser.value_of("ring_name") {
-        // We'll validate the ring when we attempt to map it:
-
-        result.ring_name = String::from(ring);
-    } else {
-        eprintln!("The --ring option is required");
-        process::exit(-1);
-    }
-    // Override the default port manager listen port.
-
-    if let Some(port) = parser.value_of("portman") {
-        // must parse as a u16:
-
-        if let Ok(port_num) = port.parse::<u16>() {
-            result.portman = port_num;
-        } else {
-            eprintln!("The port number {} must be an unsigned integer.", port);
-            process::exit(-1);
-        }
-    }
-    // If there's a comment set it - any sort of string is good:
-
-    if let Some(comment) = parser.value_of("comment") {
-        if comment != "" {
-            result.comment = String::from(comment);
-        }
-    }
-    result
+        .arg(
+            Arg::with_name("portman_retries")
+                .long("portman-retries")
+                .value_name("COUNT")
+                .help("Try an unreachable port manager again this many times, backing off")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("buffer_size")
+                .short("b")
+                .long("buffer-size")
+                .value_name("BYTES")
+                .help("Size of the chunks read from the ring e.g. 1048576, 512K or 1M")
+                .takes_value(true)
+                .default_value("1048576"),
+        )
+        .arg(
+            Arg::with_name("timeout_ms")
+                .short("t")
+                .long("timeout-ms")
+                .value_name("MILLISECONDS")
+                .help("Maximum time each read waits for data; 0 polls without waiting")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("bulk")
+                .long("bulk")
+                .help("Read whole buffers for throughput rather than latency (overrides --timeout-ms)"),
+        )
+        .arg(
+            Arg::with_name("max_bytes")
+                .short("m")
+                .long("max-bytes")
+                .value_name("BYTES")
+                .help("Exit after forwarding this many bytes (K, M, G suffixes allowed)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("items")
+                .long("items")
+                .value_name("COUNT")
+                .help("Exit after forwarding this many complete ring items")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("item_align")
+                .long("item-align")
+                .help("Only write whole ring items, holding back partial ones until complete"),
+        )
+        .arg(
+            Arg::with_name("stats_interval")
+                .short("s")
+                .long("stats-interval")
+                .value_name("SECONDS")
+                .help("Print throughput statistics to stderr this often")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("progress")
+                .long("progress")
+                .help("Show progress (towards --max-bytes, if given) on stderr if it's a terminal"),
+        )
+        .arg(
+            Arg::with_name("json_stats")
+                .long("json-stats")
+                .help("Write the --stats-interval statistics as one line JSON objects"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE")
+                .help("Write the data to this file or FIFO rather than stdout")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("allow_tty")
+                .long("allow-tty")
+                .help("Write the data to stdout even if it's a terminal"),
+        )
+        .arg(
+            Arg::with_name("append")
+                .long("append")
+                .requires("output")
+                .help("Append to the --output file rather than truncating it"),
+        )
+        .arg(
+            Arg::with_name("tee")
+                .long("tee")
+                .value_name("FILE")
+                .help("Also write a copy of the output to FILE; may be repeated")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("sync_interval")
+                .long("sync-interval")
+                .value_name("SECONDS")
+                .requires("output")
+                .help("Sync the --output file to disk this often")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("compress")
+                .long("compress")
+                .value_name("METHOD")
+                .possible_values(&["none", "gzip", "zstd"])
+                .requires("output")
+                .help("Compress the --output file with gzip or zstd")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("with_header")
+                .long("with-header")
+                .help("Start the output with a header describing the stream (see framing)"),
+        )
+        .arg(
+            Arg::with_name("unix_socket")
+                .long("unix-socket")
+                .value_name("PATH")
+                .help("Write the data to the Unix domain socket at PATH rather than stdout")
+                .takes_value(true)
+                .conflicts_with("output"),
+        )
+        .arg(
+            Arg::with_name("connect")
+                .long("connect")
+                .value_name("HOST:PORT")
+                .help("Write the data to a TCP connection to HOST:PORT rather than stdout")
+                .takes_value(true)
+                .conflicts_with("output")
+                .conflicts_with("unix_socket"),
+        )
+        .arg(
+            Arg::with_name("reconnect")
+                .long("reconnect")
+                .requires("connect")
+                .help("If the --connect connection is lost, connect again rather than exit"),
+        )
+        .arg(
+            Arg::with_name("unix_listen")
+                .long("unix-listen")
+                .requires("unix_socket")
+                .help("Create the --unix-socket and wait for the reader to connect to it"),
+        )
+        .arg(
+            Arg::with_name("retry")
+                .long("retry")
+                .value_name("COUNT")
+                .help("Retry attaching this many times if the ring or ringmaster isn't ready")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("print_slot")
+                .long("print-slot")
+                .help("Print the consumer slot we were given to stderr as slot=N"),
+        )
+        .arg(
+            Arg::with_name("ringmaster_timeout")
+                .long("ringmaster-timeout")
+                .value_name("SECONDS")
+                .help("How long to wait for the ringmaster to accept us and reply")
+                .takes_value(true)
+                .default_value("5"),
+        )
+        .arg(
+            Arg::with_name("ringmaster_index")
+                .long("ringmaster-index")
+                .value_name("INDEX")
+                .help("Which ringmaster to use (from 0) if the port manager has several")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ringmaster_service")
+                .long("ringmaster-service")
+                .value_name("NAME")
+                .help("Port manager service name the ringmaster is registered as (default: RingMaster)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ringmaster_socket")
+                .long("ringmaster-socket")
+                .value_name("PATH|@NAME")
+                .conflicts_with_all(&["ringmaster_service", "ringmaster_index"])
+                .help("Reach the local ringmaster on this Unix socket (@NAME: abstract) rather than TCP")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("slot")
+                .long("slot")
+                .value_name("N")
+                .help("Consumer slot to ask for in local rings (default: the first free one)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keepalive")
+                .long("keepalive")
+                .value_name("SECONDS")
+                .help("Probe the ringmaster connection when idle this long; exit if it's dead")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("frame")
+                .long("frame")
+                .help("Frame the data even from a single ring (always done for several rings)"),
+        )
+        .arg(
+            Arg::with_name("exit_on_idle")
+                .long("exit-on-idle")
+                .value_name("SECONDS")
+                .help("Exit once a ring has had no data and no producer for this long")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rate_limit")
+                .long("rate-limit")
+                .value_name("BYTES_PER_SEC")
+                .help("Limit the output to this many bytes/sec e.g. 10M (K, M, G suffixes allowed)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("slow_ms")
+                .long("slow-ms")
+                .value_name("MILLISECONDS")
+                .help("Warn when a write to the output blocks for at least this long")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("drop_on_slow")
+                .long("drop-on-slow")
+                .requires("slow_ms")
+                .help("After a slow write, drop data for --slow-ms rather than block (LOSES DATA)"),
+        )
+        .arg(
+            Arg::with_name("queue_depth")
+                .long("queue-depth")
+                .value_name("GETS")
+                .help("Write the output from a separate thread, queuing up to GETS reads")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("buffer_pool")
+                .long("buffer-pool")
+                .value_name("BUFFERS")
+                .requires("queue_depth")
+                .help("Buffers recycled between reading and the output thread (default: --queue-depth + 2)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("buffer_count")
+                .long("buffer-count")
+                .value_name("N")
+                .conflicts_with_all(&["queue_depth", "buffer_pool"])
+                .help("Write the output from a separate thread, cycling N (at least 2) buffers with it")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("skip_backlog")
+                .long("skip-backlog")
+                .help("Discard the data already in the ring(s); forward only newer data"),
+        )
+        .arg(
+            Arg::with_name("reattach")
+                .long("reattach")
+                .help("Attach again when a ring is recreated or the remote ringmaster drops us"),
+        )
+        .arg(
+            Arg::with_name("checksum")
+                .long("checksum")
+                .help("On a clean exit print bytes=N crc32=0x... for the data written to stderr"),
+        )
+        .arg(
+            Arg::with_name("latency_histogram")
+                .long("latency-histogram")
+                .help("On a clean exit print histograms of the time between gets and their sizes"),
+        )
+        .arg(
+            Arg::with_name("stop_on_stdin_eof")
+                .long("stop-on-stdin-eof")
+                .help("Stop cleanly when stdin is closed; anything read from it is discarded"),
+        )
+        .arg(
+            Arg::with_name("require_producer")
+                .long("require-producer")
+                .help("Fail rather than wait if a ring has no producer"),
+        )
+        .arg(
+            Arg::with_name("list")
+                .long("list")
+                .help("List the ring buffers in the directory to stderr and exit"),
+        )
+        .arg(
+            Arg::with_name("discover")
+                .long("discover")
+                .conflicts_with("list")
+                .help("List the rings the ringmaster knows to stderr and exit"),
+        )
+        .arg(
+            Arg::with_name("dry_run")
+                .long("dry-run")
+                .help("Check the ring(s) can be attached then exit without forwarding data"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .help("More diagnostics on stderr; -v for debug, -vv for trace"),
+        )
+        .arg(
+            Arg::with_name("log_file")
+                .long("log-file")
+                .value_name("PATH")
+                .help("Append diagnostics to PATH instead of stderr")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("stderr_prefix")
+                .long("stderr-prefix")
+                .value_name("TEXT")
+                .help("Start each diagnostic line with TEXT (default: the ring name(s); empty for none)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("idle_sleep")
+                .long("idle-sleep")
+                .value_name("MS")
+                .help("When the rings are idle, sleep between reads, backing off up to MS milliseconds")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("idle_after")
+                .long("idle-after")
+                .value_name("TIMEOUTS")
+                .requires("idle_sleep")
+                .help("Timeouts in a row before --idle-sleep starts (default 100)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("flush_policy")
+                .long("flush-policy")
+                .value_name("POLICY")
+                .help("When to flush the output: each (read), idle (default) or bytes:N")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("first_byte_timeout")
+                .long("first-byte-timeout")
+                .value_name("SECONDS")
+                .help("Exit with status 5 if no data arrives this long after attaching")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("heartbeat")
+                .long("heartbeat")
+                .value_name("SECONDS")
+                .help("Log a heartbeat line when no data has arrived for this long")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("quiet_timeouts")
+                .long("quiet-timeouts")
+                .help("Don't log reads that time out, even with -v"),
+        )
+        .arg(
+            Arg::with_name("watchdog")
+                .long("watchdog")
+                .value_name("SECONDS")
+                .help("Exit with an error if a write to the output blocks this long")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pidfile")
+                .long("pidfile")
+                .value_name("PATH")
+                .help("Once attached, write our process id to PATH; removed when we exit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("name_suffix")
+                .long("name-suffix")
+                .value_name("NAME")
+                .help("Tag the process title, log and JSON stats lines with NAME")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("comment")
+                .short("c")
+                .long("comment")
+                .value_name("COMMENT")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    // Set up logging first so the rest of the argument checking can use it.
+    // The --stderr-prefix (by default the ring names) starts each line and a
+    // --name-suffix tags it, so instances can be told apart.  Until then,
+    // all we can do with a bad --log-file is say so on stderr:
+
+    let name_suffix = parser.value_of("name_suffix").map(String::from);
+    let prefix = match parser.value_of_os("stderr_prefix") {
+        Some(prefix) => prefix.to_string_lossy().into_owned(),
+        None => match parser.values_of_os("ring_name") {
+            Some(rings) => rings
+                .map(|ring| ring.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join(","),
+            None => env_setting("RINGTOSTDOUT_RING")
+                .map(|ring| ring.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        },
+    };
+    let log_file = parser.value_of_os("log_file").map(|path| {
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|e| {
+                eprintln!(
+                    "ERROR: Unable to open the log file {}: {}",
+                    path::Path::new(path).display(),
+                    e
+                );
+                process::exit(EXIT_USAGE);
+            })
+    });
+    init_logging(
+        parser.occurrences_of("verbose"),
+        &prefix,
+        name_suffix.clone(),
+        log_file,
+    );
+    if let Some(suffix) = &name_suffix {
+        if suffix.is_empty()
+            || !suffix
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+        {
+            error!(
+                "The name suffix {:?} must be letters, digits, '.', '_' and '-'",
+                suffix
+            );
+            process::exit(EXIT_USAGE);
+        }
+        debug!("This instance is named ring2stdout-{}", suffix);
+    }
+    result.options.name = name_suffix.clone();
+    result.name_suffix = name_suffix;
+    result.pidfile = parser.value_of("pidfile").map(String::from);
+
+    // The directory is --directory, else $RINGTOSTDOUT_DIR, else the
+    // default.  It must exist unless --mkdir says to make it:
+
+    let directory = parser
+        .value_of_os("directory")
+        .map(path::PathBuf::from)
+        .or_else(|| env_setting("RINGTOSTDOUT_DIR").map(path::PathBuf::from))
+        .unwrap_or_else(|| result.directory.clone());
+
+    // With --mkdir a missing directory is created (never the ring).
+
+    if parser.is_present("mkdir") && !directory.exists() {
+        if let Err(e) = fs::create_dir_all(&directory) {
+            error!(
+                "Unable to create the directory {}: {}",
+                directory.display(),
+                e
+            );
+            process::exit(EXIT_USAGE);
+        }
+        info!("Created the ring directory {}", directory.display());
+    }
+    // With --no-directory-check we don't insist on being able to list it;
+    // problems then show up when the ring is mapped.
+
+    let checked = if parser.is_present("no_directory_check") {
+        Ok(())
+    } else {
+        fs::read_dir(&directory).map(|_| ())
+    };
+    if let Err(e) = checked {
+        let directory = directory.display();
+        match e.kind() {
+            io::ErrorKind::NotFound => error!("{} does not exist", directory),
+            io::ErrorKind::PermissionDenied => {
+                error!(
+                    "{} exists but you don't have permission to read it",
+                    directory
+                )
+            }
+            io::ErrorKind::NotADirectory => {
+                error!("{} exists but is not a directory", directory)
+            }
+            _ => error!("{} Must be a readable directory: {}", directory, e),
+        }
+        process::exit(EXIT_USAGE);
+    } else {
+        result.directory = directory;
+    }
+    result.canonicalize = !parser.is_present("no_canonicalize");
+    result.require_producer = parser.is_present("require_producer");
+    result.stop_on_stdin_eof = parser.is_present("stop_on_stdin_eof");
+
+    // ring name must be present else the program can't run.  It's
+    // --ring, else $RINGTOSTDOUT_RING:
+
+    result.list = parser.is_present("list");
+    result.discover = parser.is_present("discover");
+    result.ring_names = match parser.values_of_os("ring_name") {
+        Some(rings) => rings.map(OsString::from).collect(),
+        None => env_setting("RINGTOSTDOUT_RING").into_iter().collect(),
+    };
+    if !result.ring_names.is_empty() {
+        // A ring name is a file in the directory so it can't have a
+        // directory part that could take us out of it.  Otherwise,
+        // we'll validate the rings when we attempt to map them:
+
+        for ring in &result.ring_names {
+            if !ringmaster_client::is_simple_ring_name(ring) {
+                error!(
+                    "The ring name '{}' must be a plain file name without directories or '..'",
+                    ring.to_string_lossy()
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    } else if !result.list && !result.discover {
+        error!("The --ring option (or RINGTOSTDOUT_RING) is required");
+        process::exit(EXIT_USAGE);
+    }
+    // A host means the ring is remote:
+
+    if let Some(host) = parser.value_of("host") {
+        result.host = Some(String::from(host));
+    }
+    // Override the default port manager listen port.
+
+    if let Some(port) = parser.value_of("portman") {
+        match parse_port(port) {
+            Ok(port_num) => result.portman = port_num,
+            Err(problem) => {
+                error!("The port number {} {}", port, problem);
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    // Host of the port manager if it's not the default:
+
+    if let Some(host) = parser.value_of("portman_host") {
+        result.portman_host = Some(String::from(host));
+    }
+    // How hard to try a port manager that isn't answering (yet):
+
+    if let Some(retries) = parser.value_of("portman_retries") {
+        match retries.parse::<u32>() {
+            Ok(n) => result.portman_retries = n,
+            Err(_) => {
+                error!(
+                    "The port manager retry count {} must be an unsigned integer",
+                    retries
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    // The buffer size must be a nonzero size, possibly with a K/M/G suffix:
+
+    if let Some(size) = parser.value_of("buffer_size") {
+        match parse_size(size) {
+            Some(0) => {
+                error!("The buffer size must be greater than zero");
+                process::exit(EXIT_USAGE);
+            }
+            Some(n) => result.options.buffer_size = n,
+            None => {
+                error!(
+                    "The buffer size {} must be an unsigned integer optionally followed by K, M or G",
+                    size
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    // The read timeout.  Zero is legal and means the ring is polled
+    // without waiting at all (a busy poll).
+
+    if let Some(timeout) = parser.value_of("timeout_ms") {
+        if let Ok(ms) = timeout.parse::<u32>() {
+            result.options.timeout = Duration::from_millis(u64::from(ms));
+        } else {
+            error!("The timeout {} must be an unsigned integer", timeout);
+            process::exit(EXIT_USAGE);
+        }
+    }
+    result.options.bulk = parser.is_present("bulk");
+    // Optional limit on the number of bytes to forward:
+
+    if let Some(limit) = parser.value_of("max_bytes") {
+        match parse_size(limit) {
+            Some(n) => result.options.max_bytes = Some(n as u64),
+            None => {
+                error!(
+                    "The byte limit {} must be an unsigned integer optionally followed by K, M or G",
+                    limit
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    // Optional limit on the number of ring items to forward:
+
+    if let Some(items) = parser.value_of("items") {
+        match items.parse::<u64>() {
+            Ok(n) if n > 0 => result.options.max_items = Some(n),
+            _ => {
+                error!("The item count {} must be a positive integer", items);
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    result.options.item_align = parser.is_present("item_align");
+    // Statistics interval must be a positive number of seconds:
+
+    if let Some(interval) = parser.value_of("stats_interval") {
+        match interval.parse::<u64>() {
+            Ok(n) if n > 0 => result.options.stats_interval = Some(Duration::from_secs(n)),
+            _ => {
+                error!(
+                    "The stats interval {} must be a positive integer number of seconds",
+                    interval
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    // Output file; it's opened (and so validated) before we attach:
+
+    if let Some(output) = parser.value_of("output") {
+        result.output = Some(String::from(output));
+    }
+    result.append = parser.is_present("append");
+    if let Some(tees) = parser.values_of("tee") {
+        result.tee = tees.map(String::from).collect();
+    }
+
+    // How often the output file is synced to disk:
+
+    if let Some(interval) = parser.value_of("sync_interval") {
+        match interval.parse::<u64>() {
+            Ok(n) if n > 0 => result.sync_interval = Some(Duration::from_secs(n)),
+            _ => {
+                error!(
+                    "The sync interval {} must be a positive integer number of seconds",
+                    interval
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    result.compress = match parser.value_of("compress") {
+        Some("gzip") => Compression::Gzip,
+        Some("zstd") => Compression::Zstd,
+        _ => Compression::None,
+    };
+    result.with_header = parser.is_present("with_header");
+
+    // Unix domain socket output; also opened before we attach:
+
+    if let Some(socket) = parser.value_of("unix_socket") {
+        result.unix_socket = Some(String::from(socket));
+    }
+    result.unix_listen = parser.is_present("unix_listen");
+
+    // TCP output; connected before we attach:
+
+    if let Some(address) = parser.value_of("connect") {
+        match address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                result.connect = Some(String::from(address));
+            }
+            _ => {
+                error!("The --connect address {} must be HOST:PORT", address);
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    result.reconnect = parser.is_present("reconnect");
+
+    // Number of attach retries:
+
+    if let Some(retries) = parser.value_of("retry") {
+        if let Ok(n) = retries.parse::<u32>() {
+            result.retries = n;
+        } else {
+            error!("The retry count {} must be an unsigned integer", retries);
+            process::exit(EXIT_USAGE);
+        }
+    }
+    result.print_slot = parser.is_present("print_slot");
+    result.options.frame = parser.is_present("frame");
+    result.dry_run = parser.is_present("dry_run");
+    result.allow_tty = parser.is_present("allow_tty");
+    result.options.checksum = parser.is_present("checksum");
+    result.options.latency_histogram = parser.is_present("latency_histogram");
+    result.options.skip_backlog = parser.is_present("skip_backlog");
+    result.options.reattach = parser.is_present("reattach");
+    result.options.json_stats = parser.is_present("json_stats");
+
+    // The progress line is only for people, so only goes to a terminal:
+
+    if parser.is_present("progress") {
+        if io::stderr().is_terminal() {
+            result.options.progress = true;
+        } else {
+            debug!("stderr is not a terminal; --progress ignored");
+        }
+    }
+
+    // The ringmaster timeout must be a nonzero number of seconds:
+
+    if let Some(timeout) = parser.value_of("ringmaster_timeout") {
+        match timeout.parse::<u64>() {
+            Ok(n) if n > 0 => result.ringmaster_timeout = Duration::from_secs(n),
+            _ => {
+                error!(
+                    "The ringmaster timeout {} must be a positive integer number of seconds",
+                    timeout
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    // Output rate limit, a nonzero number of bytes/sec:
+
+    if let Some(rate) = parser.value_of("rate_limit") {
+        match parse_size(rate) {
+            Some(0) => {
+                error!("The rate limit must be greater than zero");
+                process::exit(EXIT_USAGE);
+            }
+            Some(n) => result.options.rate_limit = Some(n as u64),
+            None => {
+                error!(
+                    "The rate limit {} must be an unsigned integer optionally followed by K, M or G",
+                    rate
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    // Threshold for warning about (and maybe dropping data for) a slow
+    // downstream:
+
+    if let Some(slow) = parser.value_of("slow_ms") {
+        match slow.parse::<u64>() {
+            Ok(n) if n > 0 => result.options.slow_threshold = Some(Duration::from_millis(n)),
+            _ => {
+                error!(
+                    "The slow write threshold {} must be a positive integer number of milliseconds",
+                    slow
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    result.options.drop_on_slow = parser.is_present("drop_on_slow");
+
+    // Depth of the queue to a separate output thread:
+
+    if let Some(depth) = parser.value_of("queue_depth") {
+        match depth.parse::<usize>() {
+            Ok(n) if n > 0 => result.options.queue_depth = Some(n),
+            _ => {
+                error!("The queue depth {} must be a positive integer", depth);
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    if let Some(pool) = parser.value_of("buffer_pool") {
+        match pool.parse::<usize>() {
+            Ok(n) => result.options.buffer_pool = Some(n),
+            Err(_) => {
+                error!("The buffer pool size {} must be an unsigned integer", pool);
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    if let Some(count) = parser.value_of("buffer_count") {
+        match count.parse::<usize>() {
+            Ok(n) if n >= 2 => {
+                result.options.queue_depth = Some(n - 1);
+                result.options.buffer_pool = Some(n);
+            }
+            _ => {
+                error!(
+                    "The buffer count {} must be an integer of at least 2",
+                    count
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+
+    // Index of the ringmaster to use if there are several:
+
+    if let Some(index) = parser.value_of("ringmaster_index") {
+        if let Ok(n) = index.parse::<usize>() {
+            result.ringmaster_index = Some(n);
+        } else {
+            error!("The ringmaster index {} must be an unsigned integer", index);
+            process::exit(EXIT_USAGE);
+        }
+    }
+    // The ringmaster's service name.  Port manager listings are white
+    // space separated so it has to be one word:
+
+    if let Some(service) = parser.value_of("ringmaster_service") {
+        if service.is_empty() || service.contains(char::is_whitespace) {
+            error!(
+                "The ringmaster service name '{}' must be a single word",
+                service
+            );
+            process::exit(EXIT_USAGE);
+        }
+        result.ringmaster_service = String::from(service);
+    }
+    // A Unix socket to reach the ringmaster on; @NAME is a name in the
+    // abstract namespace, as ss and netstat show them:
+
+    if let Some(socket) = parser.value_of_os("ringmaster_socket") {
+        let socket = match socket.to_str().and_then(|s| s.strip_prefix('@')) {
+            Some("") => {
+                error!("The abstract ringmaster socket name can't be empty");
+                process::exit(EXIT_USAGE);
+            }
+            Some(name) => ringmaster_client::RingMasterSocket::Abstract(String::from(name)),
+            None if socket.is_empty() => {
+                error!("The ringmaster socket path can't be empty");
+                process::exit(EXIT_USAGE);
+            }
+            None => ringmaster_client::RingMasterSocket::Path(path::PathBuf::from(socket)),
+        };
+        result.ringmaster_socket = Some(socket);
+    }
+    // Consumer slot to ask for (whether the ring has it is checked when
+    // we attach):
+
+    if let Some(slot) = parser.value_of("slot") {
+        if let Ok(n) = slot.parse::<u32>() {
+            result.slot = Some(n);
+        } else {
+            error!("The consumer slot {} must be an unsigned integer", slot);
+            process::exit(EXIT_USAGE);
+        }
+    }
+    // TCP keepalive on the ringmaster connections, which we then watch:
+
+    if let Some(keepalive) = parser.value_of("keepalive") {
+        match keepalive.parse::<u64>() {
+            Ok(n) if n > 0 => {
+                result.keepalive = Some(Duration::from_secs(n));
+                result.options.check_connections = true;
+            }
+            _ => {
+                error!(
+                    "The keepalive interval {} must be a positive integer number of seconds",
+                    keepalive
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    // Idle time after which a ring without a producer has ended:
+
+    if let Some(idle) = parser.value_of("exit_on_idle") {
+        match idle.parse::<u64>() {
+            Ok(n) if n > 0 => result.options.exit_on_idle = Some(Duration::from_secs(n)),
+            _ => {
+                error!(
+                    "The idle time {} must be a positive integer number of seconds",
+                    idle
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    // Idle sleeping; its longest sleep and how many timeouts in a row
+    // start it:
+
+    if let Some(sleep) = parser.value_of("idle_sleep") {
+        match sleep.parse::<u64>() {
+            Ok(n) if n > 0 => result.options.idle_sleep = Some(Duration::from_millis(n)),
+            _ => {
+                error!(
+                    "The idle sleep {} must be a positive integer number of milliseconds",
+                    sleep
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    if let Some(after) = parser.value_of("idle_after") {
+        if let Ok(n) = after.parse::<u32>() {
+            result.options.idle_after = n;
+        } else {
+            error!(
+                "The idle timeout count {} must be an unsigned integer",
+                after
+            );
+            process::exit(EXIT_USAGE);
+        }
+    }
+    // When to flush the output:
+
+    if let Some(policy) = parser.value_of("flush_policy") {
+        result.options.flush_policy = match parse_flush_policy(policy) {
+            Some(policy) => policy,
+            None => {
+                error!(
+                    "The flush policy {} must be each, idle or bytes:N (N > 0, K, M and G allowed)",
+                    policy
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    // Heartbeat interval while idle:
+
+    if let Some(heartbeat) = parser.value_of("heartbeat") {
+        match heartbeat.parse::<u64>() {
+            Ok(n) if n > 0 => result.options.heartbeat = Some(Duration::from_secs(n)),
+            _ => {
+                error!(
+                    "The heartbeat interval {} must be a positive integer number of seconds",
+                    heartbeat
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    result.options.quiet_timeouts = parser.is_present("quiet_timeouts");
+
+    // How long to wait for the first data:
+
+    if let Some(timeout) = parser.value_of("first_byte_timeout") {
+        match timeout.parse::<u64>() {
+            Ok(n) if n > 0 => result.options.first_data_timeout = Some(Duration::from_secs(n)),
+            _ => {
+                error!(
+                    "The first byte timeout {} must be a positive integer number of seconds",
+                    timeout
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    // How long a write can block before the watchdog gives up on
+    // downstream:
+
+    if let Some(watchdog) = parser.value_of("watchdog") {
+        match watchdog.parse::<u64>() {
+            Ok(n) if n > 0 => result.watchdog = Some(Duration::from_secs(n)),
+            _ => {
+                error!(
+                    "The watchdog time {} must be a positive integer number of seconds",
+                    watchdog
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    // If there's a comment set it, cleaned up for process displays:
+
+    if let Some(comment) = parser.value_of("comment") {
+        result.comment = clean_comment(comment);
+    }
+    result
+}
+// The value of the environment variable name if it's set and not empty.
+// The settings are paths, which needn't be UTF-8:
+//
+fn env_setting(name: &str) -> Option<OsString> {
+    std::env::var_os(name).filter(|value| !value.is_empty())
+}
+// Make a --comment safe to show in process displays: control
+// characters (including newlines) become spaces and it's cut to
+// MAX_COMMENT_CHARS characters.  Both are warned about.
+//
+fn clean_comment(comment: &str) -> String {
+    let mut result: String = comment
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    if result != comment {
+        warn!("Control characters in the --comment were replaced by spaces");
+    }
+    if let Some((cut, _)) = result.char_indices().nth(MAX_COMMENT_CHARS) {
+        result.truncate(cut);
+        warn!(
+            "The --comment was truncated to {} characters",
+            MAX_COMMENT_CHARS
+        );
+    }
+    result
+}
+// Initialize env_logger.  Everything is logged to stderr, or log_file
+// if there is one, never stdout which carries the data.  Each line starts
+// with prefix (unless it's empty) and is tagged with name_suffix.  By default info
+// and more severe messages are shown; each -v shows more.  RUST_LOG, if
+// set, overrides this.
+//
+fn init_logging(
+    verbosity: u64,
+    prefix: &str,
+    name_suffix: Option<String>,
+    log_file: Option<fs::File>,
+) {
+    let prefix = if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("{}: ", prefix)
+    };
+    let level = match verbosity {
+        0 => log::LevelFilter::Info,
+        1 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .target(match log_file {
+            Some(file) => env_logger::Target::Pipe(Box::new(file)),
+            None => env_logger::Target::Stderr,
+        })
+        .format(move |buf, record| match &name_suffix {
+            Some(suffix) => writeln!(
+                buf,
+                "{}{} [{}]: {}",
+                prefix,
+                record.level(),
+                suffix,
+                record.args()
+            ),
+            None => writeln!(buf, "{}{}: {}", prefix, record.level(), record.args()),
+        })
+        .init();
+}
+// Parse a TCP port number, 1-65535.  The error says what's wrong with
+// text, to follow it in a message.
+//
+fn parse_port(text: &str) -> Result<u16, &'static str> {
+    match text.parse::<u64>() {
+        Ok(0) => Err("can't be 0; ports are 1-65535"),
+        Ok(n) if n > u64::from(u16::MAX) => Err("is out of range; ports are 1-65535"),
+        Ok(n) => Ok(n as u16),
+        Err(_) => Err("must be an unsigned integer (1-65535)"),
+    }
+}
+// Parse a --flush-policy: each, idle or bytes:N where N is a size
+// (see parse_size) that can't be zero.
+//
+fn parse_flush_policy(text: &str) -> Option<drain::FlushPolicy> {
+    match text {
+        "each" => Some(drain::FlushPolicy::Each),
+        "idle" => Some(drain::FlushPolicy::Idle),
+        _ => match parse_size(text.strip_prefix("bytes:")?)? {
+            0 => None,
+            n => Some(drain::FlushPolicy::Bytes(n as u64)),
+        },
+    }
+}
+// Parse a size that can have a K, M or G suffix (powers of 1024)
+// e.g. 512K is 524288 and 1M is 1048576.  None is returned if the
+// string is not a valid size or the result overflows a usize.
+//
+fn parse_size(text: &str) -> Option<usize> {
+    let text = text.trim();
+    let (digits, multiplier) = match text.chars().last() {
+        Some('k') | Some('K') => (&text[..text.len() - 1], 1024),
+        Some('m') | Some('M') => (&text[..text.len() - 1], 1024 * 1024),
+        Some('g') | Some('G') => (&text[..text.len() - 1], 1024 * 1024 * 1024),
+        _ => (text, 1),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
 }
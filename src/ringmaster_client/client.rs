@@ -10,30 +10,57 @@
 //! a connection to the ring master as long as it needs to be a
 //! client.
 //!
-//! As such, this provides only support for clients to local rings.
-//! We would need to implement the REMOTE request to support remote
-//! ring buffer access.
+//! Consumers of remote rings are supported via the REMOTE request.
+//! In that case the remote ringmaster arranges for the ring's data
+//! to be sent to us over the socket used to make the request.
+//!
+//! The local ringmaster is normally reached over TCP on the port the
+//! port manager gives for it, but it can also be reached over a Unix
+//! domain socket (see set_ringmaster_socket).
 
-use nscldaq_ringbuffer::ringbuffer::{consumer, producer, RingBufferMap};
+use super::rings::check_ring_format;
+use log::debug;
+use nscldaq_ringbuffer::ringbuffer::{
+    consumer, producer, RingBufferMap, ThreadSafeRingBuffer, UNUSED_ENTRY,
+};
 use portman_client;
+use std::ffi::OsStr;
 use std::fmt;
 use std::fmt::{Display, Formatter};
-use std::io::{BufRead, BufReader, Write};
-use std::net::TcpStream;
+use std::io;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
 use std::path;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 //
 // Types of errors we can produce:
 //
+#[derive(Debug)]
 pub enum Error {
     ConsumerError(consumer::Error),
     ProducerError(producer::Error),
     MapError(String),
     PortManError(portman_client::Error),
+    NoPortManager(String),
     NoRingMaster,
+    AmbiguousRingMaster(Vec<u16>),
+    BadRingMasterIndex(usize, Vec<u16>),
+    BadSlot(u32, usize),
+    SlotTaken(u32, String),
     RingMasterFail(String),
+    RingMasterNoRing(String),
+    RingMasterDenied(String),
+    RingMasterIo(io::Error),
+    RingMasterTimeout,
+    BadHost(String),
+    NoProducer,
     Unimplemented,
 }
 
@@ -50,35 +77,481 @@ impl Display for Error {
             Error::PortManError(e) => {
                 format!("Error interacting with port manager: {}", e.to_string())
             }
-            Error::NoRingMaster => format!("The ring master is not running"),
+            Error::NoPortManager(at) => format!(
+                "Unable to contact the port manager at {}; is it running?",
+                at
+            ),
+            Error::NoRingMaster => String::from(
+                "The ring master is not running (none is registered with the port manager or it refused our connection)",
+            ),
+            Error::AmbiguousRingMaster(ports) => format!(
+                "Several ringmasters are registered (ports {}); one must be chosen by index",
+                port_list(ports)
+            ),
+            Error::BadRingMasterIndex(index, ports) => format!(
+                "There is no ringmaster with index {}; the registered ringmasters are on ports {}",
+                index,
+                port_list(ports)
+            ),
+            Error::BadSlot(slot, max) => format!(
+                "There is no consumer slot {}; the ring has slots 0 through {}",
+                slot,
+                max.saturating_sub(1)
+            ),
+            Error::SlotTaken(slot, why) => format!("Consumer slot {} is taken: {}", slot, why),
             Error::RingMasterFail(s) => format!("Interaction with ringmaster failed: {}", s),
+            Error::RingMasterNoRing(s) => {
+                format!("The ringmaster does not know the ring: {}", s)
+            }
+            Error::RingMasterDenied(s) => format!("The ringmaster refused our request: {}", s),
+            Error::RingMasterIo(e) => format!("I/O error talking to the ringmaster: {}", e),
+            Error::RingMasterTimeout => String::from("Timed out waiting for the ringmaster"),
+            Error::BadHost(h) => format!("Unable to resolve the ringmaster host {}", h),
+            Error::NoProducer => String::from("The ring has no producer"),
             Error::Unimplemented => String::from("Unimplemented operation attempted"),
         };
         write!(f, "{}", stringified)
     }
 }
+impl Error {
+    ///
+    /// Classify errors from getting data as retryable (trying again may
+    /// work) or fatal:
+    ///
+    /// *  A consumer Timeout just means there's no data yet.
+    /// *  Other consumer errors are fatal: TooMuchData means the get is
+    ///    bigger than the ring and will always be, NoFreeConsumers and
+    ///    Unimplemented don't change with time either.
+    /// *  An I/O error on a remote ring's socket is retryable if it's
+    ///    one of the kinds that mean "try again" (interrupted,
+    ///    would block, timed out), anything else means the connection is
+    ///    broken.
+    /// *  Everything else comes from setting up a client and is fatal.
+    ///
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::ConsumerError(consumer::Error::Timeout) => true,
+            Error::RingMasterIo(e) => matches!(
+                e.kind(),
+                ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
+}
+// Comma separated list of ports for error messages:
+//
+fn port_list(ports: &[u16]) -> String {
+    ports
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
 //
 // Types of clients:
 //
 pub enum ClientType {
     Consumer(consumer::Consumer),
     Producer(producer::Producer),
+    RemoteConsumer(TcpStream),
+}
+
+impl ClientType {
+    ///
+    /// The consumer slot of a local consumer.  Producers and remote
+    /// consumers (whose slot is in the remote ring) have none.
+    ///
+    pub fn slot(&self) -> Option<u32> {
+        match self {
+            ClientType::Consumer(c) => Some(c.get_index()),
+            _ => None,
+        }
+    }
+    ///
+    /// Get data from a consumer whether it's local or remote.
+    /// At most data.len() bytes are read, waiting at most timeout for
+    /// data to arrive.  If no data arrives in time,
+    /// Error::ConsumerError(consumer::Error::Timeout) is returned.
+    /// Ok(0) means the data source has gone away (a remote ringmaster closed
+    /// the connection); local rings never return it.  Producers can't be
+    /// read so they give Error::Unimplemented.
+    ///
+    pub fn timed_get(&mut self, data: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        match self {
+            ClientType::Consumer(c) => c.timed_get(data, timeout).map_err(Error::ConsumerError),
+            ClientType::RemoteConsumer(stream) => remote_timed_get(stream, data, timeout),
+            ClientType::Producer(_) => Err(Error::Unimplemented),
+        }
+    }
 }
 //
 // Struct to hold what we need to maintain a connection to the
 // ring master and to operate as a client:
-/// The purpose of the ring_master field is just to
-/// allow the socket connection to stay in scope.
+/// The purpose of the ring_master field is mostly to
+/// allow the socket connection to stay in scope (see ringmaster_stream).
+/// For local rings, ring is the mapped ring buffer, which lets us
+/// look at its status.  origin remembers what we attached to so that
+/// we can attach again.
 ///
-#[allow(dead_code)]
-#[allow(unused_variables)]
 pub struct RingClient {
     pub client: ClientType,
-    ring_master: TcpStream,
+    ring_master: RingMasterStream,
+    ring: Option<ThreadSafeRingBuffer>,
+    origin: Origin,
+}
+///
+/// A connection to a ringmaster: TCP or, for a local ringmaster reached
+/// through set_ringmaster_socket, a Unix domain socket.  It reads and
+/// writes as the stream it holds and has the few socket operations we
+/// need of either.
+///
+#[derive(Debug)]
+pub enum RingMasterStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl RingMasterStream {
+    ///
+    /// As TcpStream::set_read_timeout.
+    ///
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            RingMasterStream::Tcp(s) => s.set_read_timeout(timeout),
+            #[cfg(unix)]
+            RingMasterStream::Unix(s) => s.set_read_timeout(timeout),
+        }
+    }
+    ///
+    /// As TcpStream::set_nonblocking.
+    ///
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            RingMasterStream::Tcp(s) => s.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            RingMasterStream::Unix(s) => s.set_nonblocking(nonblocking),
+        }
+    }
+    ///
+    /// As TcpStream::peek: read what's waiting without consuming it.
+    ///
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            RingMasterStream::Tcp(s) => s.peek(buf),
+            #[cfg(unix)]
+            RingMasterStream::Unix(s) => {
+                // UnixStream::peek isn't stable yet:
+
+                let mut peeked = vec![std::mem::MaybeUninit::<u8>::uninit(); buf.len()];
+                let n = socket2::SockRef::from(s).peek(&mut peeked)?;
+                for (to, from) in buf.iter_mut().zip(&peeked[..n]) {
+                    *to = unsafe { from.assume_init() };
+                }
+                Ok(n)
+            }
+        }
+    }
+}
+impl Read for RingMasterStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            RingMasterStream::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            RingMasterStream::Unix(s) => s.read(buf),
+        }
+    }
+}
+impl Write for RingMasterStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            RingMasterStream::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            RingMasterStream::Unix(s) => s.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RingMasterStream::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            RingMasterStream::Unix(s) => s.flush(),
+        }
+    }
+}
+///
+/// A Unix domain socket a ringmaster listens on (see
+/// set_ringmaster_socket):
+///
+/// *  Path - a socket file.
+/// *  Abstract - a name in the Linux abstract socket namespace, which
+///    has no file.  Elsewhere connecting to one fails.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RingMasterSocket {
+    Path(PathBuf),
+    Abstract(String),
 }
 
-#[allow(non_upper_case_globals)]
-static mut portman_port: u16 = 30000;
+impl Display for RingMasterSocket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RingMasterSocket::Path(path) => write!(f, "{}", path.display()),
+            RingMasterSocket::Abstract(name) => write!(f, "@{}", name),
+        }
+    }
+}
+// How we reach the local ringmaster: over TCP on a port (from the port
+// manager) or over the Unix socket set_ringmaster_socket chose:
+//
+enum Transport {
+    Tcp(u16),
+    Unix(RingMasterSocket),
+}
+// Where a client's ring is.  For local rings we keep the identity
+// (device and inode) of the ring file we mapped so we can tell if it's
+// been deleted or replaced.
+//
+enum Origin {
+    Local {
+        path: PathBuf,
+        file_id: Option<(u64, u64)>,
+    },
+    Remote {
+        host: String,
+        ring: String,
+    },
+}
+
+impl RingClient {
+    ///
+    /// Whether the ring currently has a producer attached.  None
+    /// is returned for remote rings as we can't see their status.
+    ///
+    pub fn producer_attached(&self) -> Option<bool> {
+        self.ring
+            .as_ref()
+            .map(|ring| ring.lock().unwrap().get_usage().producer_pid != UNUSED_ENTRY)
+    }
+    ///
+    /// The connection to the ringmaster that registered this client, for
+    /// callers that want to talk to the ringmaster themselves.  The
+    /// ringmaster forgets the client when this connection closes, so it
+    /// must not be shut down while the client is in use.  For a remote
+    /// ring the connection also carries the ring's data, so anything
+    /// read from it is data lost to timed_get.
+    ///
+    pub fn ringmaster_stream(&self) -> &RingMasterStream {
+        &self.ring_master
+    }
+    ///
+    /// Mutable access to the ringmaster connection, e.g. to write a
+    /// request to it.  The same cautions as for ringmaster_stream apply.
+    ///
+    pub fn ringmaster_stream_mut(&mut self) -> &mut RingMasterStream {
+        &mut self.ring_master
+    }
+    ///
+    /// Whether the ring file we mapped has been deleted or replaced
+    /// by a new ring (e.g. because the ring was recreated).  Our
+    /// mapping still works but no new data will ever appear in it.
+    /// Remote rings are never replaced as far as we can tell.
+    ///
+    pub fn ring_replaced(&self) -> bool {
+        match &self.origin {
+            Origin::Local { path, file_id } => match ring_file_id(path) {
+                None => true,
+                id => id != *file_id,
+            },
+            Origin::Remote { .. } => false,
+        }
+    }
+    ///
+    /// Check that our connection to the local ringmaster is still there.
+    /// The ringmaster doesn't send local clients anything, so we only
+    /// look for the connection having been closed or having failed (e.g.
+    /// because TCP keepalive found it dead, see set_ringmaster_keepalive).
+    /// A remote consumer's connection carries the data so its failures
+    /// show up when we read and we don't look here.
+    ///
+    pub fn ringmaster_connected(&self) -> Result<(), Error> {
+        if let Origin::Remote { .. } = self.origin {
+            return Ok(());
+        }
+        self.ring_master
+            .set_nonblocking(true)
+            .map_err(Error::RingMasterIo)?;
+        let mut byte = [0_u8; 1];
+        let result = match self.ring_master.peek(&mut byte) {
+            Ok(0) => Err(Error::RingMasterIo(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "the ringmaster closed our connection",
+            ))),
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(Error::RingMasterIo(e)),
+        };
+        self.ring_master
+            .set_nonblocking(false)
+            .map_err(Error::RingMasterIo)?;
+        result
+    }
+    ///
+    /// Attach again to the same ring as the same kind of client.  This
+    /// is for when the ring was recreated (see ring_replaced) or the
+    /// remote ringmaster dropped us.  Only on success is the old client
+    /// (and its ringmaster connection) replaced by the new one.
+    ///
+    pub fn reattach(&mut self) -> Result<(), Error> {
+        let new_client = match (&self.origin, &self.client) {
+            (Origin::Local { path, .. }, ClientType::Producer(_)) => attach_producer(path)?,
+            (Origin::Local { path, .. }, _) => attach_consumer(path)?,
+            (Origin::Remote { host, ring }, _) => attach_remote_consumer(host, ring)?,
+        };
+        *self = new_client;
+        Ok(())
+    }
+}
+
+///
+/// Something we can read ring data from.  The drain loop only needs this
+/// so it doesn't care whether it reads a local consumer, a remote consumer
+/// or e.g. a fake used for testing.
+///
+/// *  timed_get - as ClientType::timed_get; Ok(0) means the data
+///    source has gone away and a timeout is
+///    Error::ConsumerError(consumer::Error::Timeout).
+/// *  producer_attached - whether the ring has a producer, None if we
+///    can't tell (the default).
+/// *  replaced - whether the ring we read has been replaced by a new one
+///    so we should reattach.  By default false.
+/// *  reattach - attach to the ring again.  By default
+///    Error::Unimplemented.
+/// *  connected - check that our connection to the ringmaster is still
+///    alive, an error if not.  By default it always is.
+///
+pub trait RingReader {
+    fn timed_get(&mut self, data: &mut [u8], timeout: Duration) -> Result<usize, Error>;
+    fn producer_attached(&self) -> Option<bool> {
+        None
+    }
+    fn replaced(&self) -> bool {
+        false
+    }
+    fn reattach(&mut self) -> Result<(), Error> {
+        Err(Error::Unimplemented)
+    }
+    fn connected(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+impl RingReader for ClientType {
+    fn timed_get(&mut self, data: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        ClientType::timed_get(self, data, timeout)
+    }
+}
+impl RingReader for RingClient {
+    fn timed_get(&mut self, data: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        self.client.timed_get(data, timeout)
+    }
+    fn producer_attached(&self) -> Option<bool> {
+        RingClient::producer_attached(self)
+    }
+    fn replaced(&self) -> bool {
+        self.ring_replaced()
+    }
+    fn reattach(&mut self) -> Result<(), Error> {
+        RingClient::reattach(self)
+    }
+    fn connected(&self) -> Result<(), Error> {
+        self.ringmaster_connected()
+    }
+}
+// Boxed readers let different kinds of readers be drained together:
+
+impl<R: RingReader + ?Sized> RingReader for Box<R> {
+    fn timed_get(&mut self, data: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        (**self).timed_get(data, timeout)
+    }
+    fn producer_attached(&self) -> Option<bool> {
+        (**self).producer_attached()
+    }
+    fn replaced(&self) -> bool {
+        (**self).replaced()
+    }
+    fn reattach(&mut self) -> Result<(), Error> {
+        (**self).reattach()
+    }
+    fn connected(&self) -> Result<(), Error> {
+        (**self).connected()
+    }
+}
+
+// The port manager port.  This is atomic so that it can be modified
+// and read safely from any thread:
+
+static PORTMAN_PORT: AtomicU16 = AtomicU16::new(30000);
+
+// How many times we try the port manager again if we can't reach it:
+
+static PORTMAN_RETRIES: AtomicU32 = AtomicU32::new(0);
+
+// The host the port manager runs in.  None means the local one which
+// we talk to with portman_client (remote rings use the ring's host).
+
+static PORTMAN_HOST: Mutex<Option<String>> = Mutex::new(None);
+
+// The host the ringmaster runs in.  None means the default, the
+// loopback address (127.0.0.1, or ::1 if that fails).
+
+static RINGMASTER_HOST: Mutex<Option<String>> = Mutex::new(None);
+
+// The Unix domain socket the local ringmaster listens on.  None means we
+// reach it over TCP.
+
+static RINGMASTER_SOCKET: Mutex<Option<RingMasterSocket>> = Mutex::new(None);
+
+// How long, in milliseconds, we wait to connect to the ringmaster and
+// for it to reply to a request:
+
+static RINGMASTER_TIMEOUT_MS: AtomicU64 = AtomicU64::new(5000);
+
+// Which ringmaster to use if the port manager has several registered.
+// None means there must be only one.
+
+static RINGMASTER_INDEX: Mutex<Option<usize>> = Mutex::new(None);
+
+// The service name the ringmaster registers with the port manager.
+// None means the standard one, RINGMASTER_SERVICE.
+
+static RINGMASTER_SERVICE_NAME: Mutex<Option<String>> = Mutex::new(None);
+
+///
+/// The service name ringmasters register with the port manager.
+///
+pub const RINGMASTER_SERVICE: &str = "RingMaster";
+
+// The consumer slot local consumers ask for.  None means whichever is
+// free.
+
+static CONSUMER_SLOT: Mutex<Option<u32>> = Mutex::new(None);
+
+// TCP keepalive interval, in milliseconds, for ringmaster connections.
+// 0 means no keepalive.
+
+static RINGMASTER_KEEPALIVE_MS: AtomicU64 = AtomicU64::new(0);
+
+///
+/// Turn on TCP keepalive for the ringmaster connections of clients
+/// created from now on.  After interval without traffic a probe is
+/// sent, and again every interval; if a few go unanswered the connection
+/// is dead and fails.  None (the default) turns keepalive off.  See
+/// RingClient::ringmaster_connected.
+///
+pub fn set_ringmaster_keepalive(interval: Option<Duration>) {
+    let ms = interval.map(|i| std::cmp::max(i.as_millis() as u64, 1));
+    RINGMASTER_KEEPALIVE_MS.store(ms.unwrap_or(0), Ordering::SeqCst);
+}
 
 ///
 /// When we return a result, this is the type we return:
@@ -90,7 +563,87 @@ pub type RingClientResult = Result<RingClient, Error>;
 /// port manager operations:
 ///
 pub fn set_portman_port(new_port: u16) {
-    unsafe { portman_port = new_port };
+    PORTMAN_PORT.store(new_port, Ordering::SeqCst);
+}
+
+///
+/// Set how many times looking up the ringmaster tries again when the
+/// port manager can't be reached (Error::NoPortManager), e.g. because it's
+/// still starting.  The waits between tries start at 100ms and double up
+/// to 5 seconds.  Only connecting counts: a port manager that answers
+/// without a RingMaster (Error::NoRingMaster) isn't asked again.
+/// The default is 0, fail right away.
+///
+pub fn set_portman_retries(retries: u32) {
+    PORTMAN_RETRIES.store(retries, Ordering::SeqCst);
+}
+
+///
+/// Query the port manager in host for the ringmaster's port rather
+/// than the local one (or, for remote rings, the one in the ring's
+/// host).
+///
+pub fn set_portman_host(host: &str) {
+    *PORTMAN_HOST.lock().unwrap() = Some(String::from(host));
+}
+
+///
+/// Override the host used to contact the ringmaster for
+/// future CONNECT operations.  The default is the loopback
+/// address, 127.0.0.1 or, if nothing listens there, ::1.
+///
+pub fn set_ringmaster_host(host: &str) {
+    *RINGMASTER_HOST.lock().unwrap() = Some(String::from(host));
+}
+
+///
+/// Reach the local ringmaster through socket rather than TCP, e.g. on
+/// deployments where it listens on an abstract Unix socket.  The port
+/// manager isn't asked where it is then (though find_ringmaster_port
+/// still asks it) and set_ringmaster_host and set_ringmaster_keepalive
+/// don't apply.  Remote rings are still reached over TCP.  None (the
+/// default) goes back to TCP.
+///
+pub fn set_ringmaster_socket(socket: Option<RingMasterSocket>) {
+    *RINGMASTER_SOCKET.lock().unwrap() = socket;
+}
+
+///
+/// Override how long we wait when connecting to the ringmaster (or a
+/// remote port manager) and for its replies.  The default is 5 seconds.
+/// If exceeded, the operation fails with Error::RingMasterTimeout.
+///
+pub fn set_ringmaster_timeout(timeout: Duration) {
+    RINGMASTER_TIMEOUT_MS.store(timeout.as_millis() as u64, Ordering::SeqCst);
+}
+
+///
+/// Choose which ringmaster is used when the port manager has several
+/// registered.  The index is into the port manager's list of RingMaster
+/// services.  With None (the default) there must be exactly one, if there
+/// are several, attaching fails with Error::AmbiguousRingMaster.
+///
+pub fn set_ringmaster_index(index: Option<usize>) {
+    *RINGMASTER_INDEX.lock().unwrap() = index;
+}
+
+///
+/// Look the ringmaster up in the port manager under service rather than
+/// RINGMASTER_SERVICE, e.g. for a test ringmaster or a non-standard
+/// deployment.
+///
+pub fn set_ringmaster_service(service: &str) {
+    *RINGMASTER_SERVICE_NAME.lock().unwrap() = Some(String::from(service));
+}
+
+///
+/// Choose the consumer slot attach_consumer (and so reattaching) asks
+/// for.  With None (the default) the first free slot is used.  If the
+/// slot isn't in the ring, attaching fails with Error::BadSlot; if
+/// another consumer has it, with Error::SlotTaken.
+///
+pub fn set_consumer_slot(slot: Option<u32>) {
+    *CONSUMER_SLOT.lock().unwrap() = slot;
 }
 
 /// Create a consumer of ring data.
@@ -101,32 +654,75 @@ pub fn set_portman_port(new_port: u16) {
 /// *  send the appropriate CONNECT message to the ring master.
 ///
 /// On success we're going to return a struct that consists of,
-/// in order, the Consumer object we created and the TcpStream
+/// in order, the Consumer object we created and the RingMasterStream
 /// that's holding the connection to the ring master.
 ///
-pub fn attach_consumer(ring_buffer_file: &str) -> RingClientResult {
-    match get_ringmaster_port() {
-        Ok(port) => match RingBufferMap::new(ring_buffer_file) {
-            Ok(raw_map) => {
-                let safe_map = Arc::new(Mutex::new(raw_map));
-                match consumer::Consumer::attach(&Arc::clone(&safe_map)) {
-                    Ok(consumer) => {
-                        let slot = consumer.get_index();
-                        match connect_consumer(port, &ring_name(&ring_buffer_file), slot) {
-                            Err(e) => Err(e),
-                            Ok(stream) => Ok(RingClient {
-                                client: ClientType::Consumer(consumer),
-                                ring_master: stream,
-                            }),
-                        }
+/// The path needn't be UTF-8 but nscldaq_ringbuffer can only map UTF-8
+/// paths and the ringmaster protocol is text, so others get a MapError.
+///
+pub fn attach_consumer<P: AsRef<Path>>(ring_buffer_file: P) -> RingClientResult {
+    attach_client(ring_buffer_file.as_ref(), |map, transport, ring, origin| {
+        match attach_consumer_slot(map) {
+            Ok(consumer) => {
+                let slot = consumer.get_index();
+                // If the ringmaster won't have us, it never learned
+                // of the slot; release it ourselves before failing:
+
+                let stream = match connect_consumer(transport, ring, slot) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        debug!("Ringmaster refused consumer.{}; freeing the slot", slot);
+                        drop(consumer);
+                        return Err(e);
                     }
-                    Err(e) => Err(Error::ConsumerError(e)),
-                }
+                };
+                Ok(RingClient {
+                    client: ClientType::Consumer(consumer),
+                    ring_master: stream,
+                    ring: Some(Arc::clone(map)),
+                    origin,
+                })
             }
-            Err(s) => Err(Error::MapError(s)),
-        },
-        Err(e) => Err(e),
+            Err(e) => Err(e),
+        }
+    })
+}
+///
+/// Create a consumer of a ring in a remote host.
+/// This:
+///
+/// *   contacts the port manager in host to find the remote ring master.
+/// *   sends the ring master a REMOTE request for the ring.
+///
+/// Once the ring master replies "OK BINARY FOLLOWS", the ring's data
+/// is sent to us over the same socket, so the RemoteConsumer contains
+/// that stream.
+///
+pub fn attach_remote_consumer(host: &str, ring: &str) -> RingClientResult {
+    let portman_host = PORTMAN_HOST
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| String::from(host));
+    let port = retry_portman(|| get_remote_ringmaster_port(&portman_host))?;
+    let stream = connect_ringmaster(&resolve(host, port)?)?;
+    let mut ring_master = RingMasterStream::Tcp(stream.try_clone().map_err(Error::RingMasterIo)?);
+    let request = format!("REMOTE {{{}}}\n", ring);
+    send_request(&mut ring_master, &request)?;
+    let line = read_reply_line(&mut ring_master)?;
+    if line.trim() != "OK BINARY FOLLOWS" {
+        return Err(reply_error(line));
     }
+    enable_keepalive(&ring_master)?;
+    Ok(RingClient {
+        client: ClientType::RemoteConsumer(stream),
+        ring_master,
+        ring: None,
+        origin: Origin::Remote {
+            host: String::from(host),
+            ring: String::from(ring),
+        },
+    })
 }
 ///
 /// Create a producer of data into a ringbuffer.
@@ -135,37 +731,87 @@ pub fn attach_consumer(ring_buffer_file: &str) -> RingClientResult {
 /// *    Attaches a ringbuffer producer to the ring if possible.
 /// *    Contacts the port manager to get the RingMaster port.
 /// *    Sends the appropriate CONNECT message to the ring master
-/// to nail down the producer slot.
+///      to nail down the producer slot.
 ///
 /// On success we return a struct that contains the producer object
-/// and the TCP/IP stream the application must keep open on the
+/// and the stream the application must keep open on the
 /// ring master.   
 ///
 ///  
-pub fn attach_producer(ring_buffer_file: &str) -> RingClientResult {
-    // Note the logic is very similar to that of the attach_consumer.
-    // with a bit of thought I could perhaps do some
-    // factorization.
-
-    match get_ringmaster_port() {
-        Ok(port) => match RingBufferMap::new(ring_buffer_file) {
-            Ok(raw_map) => {
-                let safe_map = Arc::new(Mutex::new(raw_map));
-                match producer::Producer::attach(&Arc::clone(&safe_map)) {
-                    Ok(producer) => match connect_producer(port, &ring_name(&ring_buffer_file)) {
-                        Err(e) => Err(e),
-                        Ok(stream) => Ok(RingClient {
-                            client: ClientType::Producer(producer),
-                            ring_master: stream,
-                        }),
-                    },
-                    Err(e) => Err(Error::ProducerError(e)),
-                }
+pub fn attach_producer<P: AsRef<Path>>(ring_buffer_file: P) -> RingClientResult {
+    attach_client(ring_buffer_file.as_ref(), |map, transport, ring, origin| {
+        match producer::Producer::attach(map) {
+            Ok(producer) => {
+                let stream = match connect_producer(transport, ring) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        debug!("Ringmaster refused the producer; freeing the ring");
+                        drop(producer);
+                        return Err(e);
+                    }
+                };
+                Ok(RingClient {
+                    client: ClientType::Producer(producer),
+                    ring_master: stream,
+                    ring: Some(Arc::clone(map)),
+                    origin,
+                })
             }
-            Err(s) => Err(Error::MapError(s)),
-        },
-        Err(e) => Err(e),
-    }
+            Err(e) => Err(Error::ProducerError(e)),
+        }
+    })
+}
+///
+/// What the ringmaster's LIST reply says about one of the rings it knows:
+///
+/// *  name - the ring name.
+/// *  data_bytes - the size of its data area, if the ringmaster said.
+/// *  producer - the producer's pid if there is one.
+/// *  consumers - the number of consumers attached.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedRing {
+    pub name: String,
+    pub data_bytes: Option<usize>,
+    pub producer: Option<u32>,
+    pub consumers: usize,
+}
+
+///
+/// Ask the ringmaster (reached as for attach_consumer) for the rings it
+/// knows, sorted by name.  This is its view, which includes rings that
+/// aren't in the directory we'd look in.  The ringmaster replies to LIST
+/// with "OK" and then a line holding a Tcl list of {name usage} pairs,
+/// where usage is
+///
+///    {size free max_consumers producer_pid max_get min_get {{pid backlog}...}}
+///
+/// with a producer_pid of -1 if there's no producer.  Whatever of the
+/// usage we can't make sense of is left out (None, or no consumers).
+///
+pub fn list_ringmaster_rings() -> Result<Vec<ListedRing>, Error> {
+    let mut stream = ringmaster_request(&find_transport()?, "LIST\n")?;
+    let timeout = Duration::from_millis(RINGMASTER_TIMEOUT_MS.load(Ordering::SeqCst));
+    stream
+        .set_read_timeout(Some(timeout))
+        .map_err(Error::RingMasterIo)?;
+    let line = read_reply_line(&mut stream)?;
+    let mut rings: Vec<ListedRing> = tcl_list(&line)
+        .iter()
+        .filter_map(|ring| listed_ring(ring))
+        .collect();
+    rings.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(rings)
+}
+///
+/// Find the port the ringmaster is listening on without attaching to
+/// anything.  We ask the local port manager (set_portman_port) unless
+/// set_portman_host chose another, trying again as set_portman_retries
+/// says, and pick the ringmaster as set_ringmaster_index says.  This is
+/// the lookup attach_consumer and attach_producer do first.
+///
+pub fn find_ringmaster_port() -> Result<u16, Error> {
+    retry_portman(ask_port_manager)
 }
 /*-----------------------------------------------------------------
     Private functions.
@@ -174,92 +820,701 @@ pub fn attach_producer(ring_buffer_file: &str) -> RingClientResult {
 
 */
 
-// Return the port the ringmaster is listening on:
+// Common code for attach_consumer and attach_producer.  We locate the
+// ringmaster and map the ring buffer, then let attach do the
+// client specific work of attaching to the ring and registering
+// the client with the ringmaster.  attach gets the mapped ring,
+// how to reach the ringmaster, the ring name and the Origin for the
+// client.
+//
+fn attach_client<F>(ring_buffer_file: &Path, attach: F) -> RingClientResult
+where
+    F: FnOnce(&ThreadSafeRingBuffer, &Transport, &str, Origin) -> RingClientResult,
+{
+    check_regular_file(ring_buffer_file)?;
+    check_ring_format(ring_buffer_file).map_err(Error::MapError)?;
+    let transport = find_transport()?;
+    let name = ring_name(ring_buffer_file)?;
+    let origin = Origin::Local {
+        path: PathBuf::from(ring_buffer_file),
+        file_id: ring_file_id(ring_buffer_file),
+    };
+    let map_path = ring_buffer_file.to_str().ok_or_else(|| {
+        Error::MapError(format!(
+            "{} is not a valid UTF-8 path",
+            ring_buffer_file.display()
+        ))
+    })?;
+    match RingBufferMap::new(map_path) {
+        Ok(raw_map) => {
+            let safe_map = Arc::new(Mutex::new(raw_map));
+            attach(&safe_map, &transport, &name, origin)
+        }
+        Err(s) => Err(Error::MapError(s)),
+    }
+}
+// Attach a consumer to the mapped ring, in the slot set by
+// set_consumer_slot if there is one.  Consumer::attach always takes the
+// first free slot so we hold the free slots below the one we want
+// while it attaches, then let them go.
+//
+fn attach_consumer_slot(map: &ThreadSafeRingBuffer) -> Result<consumer::Consumer, Error> {
+    let slot = match *CONSUMER_SLOT.lock().unwrap() {
+        Some(slot) => slot,
+        None => return consumer::Consumer::attach(map).map_err(Error::ConsumerError),
+    };
+    let pid = process::id();
+    let mut held = Vec::new();
+    {
+        let mut ring = map.lock().unwrap();
+        let max = ring.max_consumers();
+        if slot as usize >= max {
+            return Err(Error::BadSlot(slot, max));
+        }
+        let owner = ring
+            .consumer(slot as usize)
+            .map_err(Error::MapError)?
+            .get_pid();
+        if owner != UNUSED_ENTRY {
+            return Err(Error::SlotTaken(slot, format!("process {} has it", owner)));
+        }
+        for n in 0..slot as usize {
+            if ring.set_consumer(n, pid).is_ok() {
+                held.push(n);
+            }
+        }
+    }
+    let result = consumer::Consumer::attach(map);
+    {
+        let mut ring = map.lock().unwrap();
+        for n in held {
+            let _ = ring.free_consumer(n, pid);
+        }
+    }
+    let consumer = result.map_err(Error::ConsumerError)?;
+    if consumer.get_index() != slot {
+        return Err(Error::SlotTaken(
+            slot,
+            String::from("another consumer attached first"),
+        ));
+    }
+    Ok(consumer)
+}
+// Mapping something that isn't a file (e.g. a directory named by mistake)
+// fails confusingly, so say what it is instead.  A path that doesn't
+// exist (yet) is left for the map to fail on.
+//
+fn check_regular_file(path: &Path) -> Result<(), Error> {
+    match std::fs::metadata(path) {
+        Ok(metadata) if !metadata.is_file() => Err(Error::MapError(format!(
+            "{} is a {}, not a ring buffer file",
+            path.display(),
+            file_type_name(&metadata.file_type())
+        ))),
+        _ => Ok(()),
+    }
+}
+#[cfg(unix)]
+fn file_type_name(file_type: &std::fs::FileType) -> &'static str {
+    use std::os::unix::fs::FileTypeExt;
+    if file_type.is_dir() {
+        "directory"
+    } else if file_type.is_fifo() {
+        "FIFO"
+    } else if file_type.is_socket() {
+        "socket"
+    } else if file_type.is_block_device() {
+        "block device"
+    } else if file_type.is_char_device() {
+        "character device"
+    } else {
+        "special file"
+    }
+}
+#[cfg(not(unix))]
+fn file_type_name(file_type: &std::fs::FileType) -> &'static str {
+    if file_type.is_dir() {
+        "directory"
+    } else {
+        "special file"
+    }
+}
+// The identity (device, inode) of a ring buffer file; None if it
+// doesn't exist.  Elsewhere we can't tell files apart, only whether
+// they exist.
+//
+#[cfg(unix)]
+fn ring_file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+}
+#[cfg(not(unix))]
+fn ring_file_id(path: &Path) -> Option<(u64, u64)> {
+    std::fs::metadata(path).ok().map(|_| (0, 0))
+}
+// Look the ringmaster up with lookup, trying again as
+// set_portman_retries says while the port manager can't be reached:
+//
+fn retry_portman<F: Fn() -> Result<u16, Error>>(lookup: F) -> Result<u16, Error> {
+    let max_delay = Duration::from_secs(5);
+    let mut delay = Duration::from_millis(100);
+    let mut retries = PORTMAN_RETRIES.load(Ordering::SeqCst);
+    loop {
+        match lookup() {
+            Err(Error::NoPortManager(at)) if retries > 0 => {
+                debug!("No port manager at {}; trying again in {:?}", at, delay);
+                std::thread::sleep(delay);
+                delay = std::cmp::min(delay * 2, max_delay);
+                retries -= 1;
+            }
+            result => return result,
+        }
+    }
+}
+// How to reach the local ringmaster: its Unix socket if
+// set_ringmaster_socket gave one, otherwise TCP to the port the port
+// manager gives:
+//
+fn find_transport() -> Result<Transport, Error> {
+    match RINGMASTER_SOCKET.lock().unwrap().clone() {
+        Some(socket) => Ok(Transport::Unix(socket)),
+        None => find_ringmaster_port().map(Transport::Tcp),
+    }
+}
+// Ask the port manager for the ringmaster's port, once:
 //
-fn get_ringmaster_port() -> Result<u16, Error> {
-    let port = unsafe { portman_port };
+fn ask_port_manager() -> Result<u16, Error> {
+    let portman_host = PORTMAN_HOST.lock().unwrap().clone();
+    if let Some(host) = portman_host {
+        return get_remote_ringmaster_port(&host);
+    }
+    let port = PORTMAN_PORT.load(Ordering::SeqCst);
     let mut client = portman_client::Client::new(port);
 
-    match client.find_by_service("RingMaster") {
-        Err(e) => Err(Error::PortManError(e)),
-        Ok(v) => {
-            if v.len() == 0 {
-                Err(Error::NoRingMaster)
-            } else {
-                Ok(v[0].port) // If there are several ports, return the first.
+    match client.find_by_service(&ringmaster_service()) {
+        Err(e) => Err(portman_failure(e, &format!("localhost:{}", port))),
+        Ok(v) => choose_ringmaster(v.iter().map(|s| s.port).collect()),
+    }
+}
+// The service name to look the ringmaster up by (see
+// set_ringmaster_service):
+//
+fn ringmaster_service() -> String {
+    RINGMASTER_SERVICE_NAME
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| String::from(RINGMASTER_SERVICE))
+}
+// Pick the ringmaster port to use from the ports of all the
+// registered RingMaster services (see set_ringmaster_index):
+//
+fn choose_ringmaster(ports: Vec<u16>) -> Result<u16, Error> {
+    debug!("Ringmaster candidates: ports {}", port_list(&ports));
+    match (*RINGMASTER_INDEX.lock().unwrap(), ports.len()) {
+        (_, 0) => Err(Error::NoRingMaster),
+        (None, 1) => Ok(ports[0]),
+        (None, _) => Err(Error::AmbiguousRingMaster(ports)),
+        (Some(i), n) if i < n => Ok(ports[i]),
+        (Some(i), _) => Err(Error::BadRingMasterIndex(i, ports)),
+    }
+}
+// Return the port the ringmaster is listening on, asking the port
+// manager in host.
+// The portman_client crate can only talk to the local port manager so we
+// do the LIST request ourselves.  The reply is "OK n" followed by n lines
+// of the form "port service user".
+//
+fn get_remote_ringmaster_port(host: &str) -> Result<u16, Error> {
+    let port = PORTMAN_PORT.load(Ordering::SeqCst);
+    let mut stream = connect_remote_port_manager(host, port)?;
+    if stream.write_all(b"LIST\n").is_err() || stream.flush().is_err() {
+        return Err(Error::NoPortManager(format!("{}:{}", host, port)));
+    }
+    let mut reader = BufReader::new(stream);
+    let header = read_portman_line(&mut reader, host, port)?;
+    let words: Vec<&str> = header.split_whitespace().collect();
+    let count = match words.as_slice() {
+        ["OK", n] => n.parse::<usize>().ok(),
+        ["FAIL", ..] => return Err(Error::PortManError(portman_client::Error::RequestDenied)),
+        _ => None,
+    };
+    let count = count.ok_or(Error::PortManError(
+        portman_client::Error::UnanticipatedReply,
+    ))?;
+
+    let service = ringmaster_service();
+    let mut ports = Vec::<u16>::new();
+    for _ in 0..count {
+        let line = read_portman_line(&mut reader, host, port)?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() != 3 {
+            return Err(Error::PortManError(
+                portman_client::Error::UnanticipatedReply,
+            ));
+        }
+        if words[1] == service {
+            match words[0].parse::<u16>() {
+                Ok(p) => ports.push(p),
+                Err(_) => {
+                    return Err(Error::PortManError(
+                        portman_client::Error::UnanticipatedReply,
+                    ))
+                }
+            }
+        }
+    }
+    choose_ringmaster(ports)
+}
+// Connect to the port manager in host on port.  As with the ringmaster,
+// connecting and then waiting for replies are limited by the ringmaster
+// timeout (set_ringmaster_timeout) and running out of time is a
+// RingMasterTimeout.  A host we can't resolve or that no address of
+// accepts us on has no port manager.
+//
+fn connect_remote_port_manager(host: &str, port: u16) -> Result<TcpStream, Error> {
+    let timeout = Duration::from_millis(RINGMASTER_TIMEOUT_MS.load(Ordering::SeqCst));
+    let at = || Error::NoPortManager(format!("{}:{}", host, port));
+    let addresses = (host, port).to_socket_addrs().map_err(|_| at())?;
+    let mut result = Err(at());
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => {
+                return match stream.set_read_timeout(Some(timeout)) {
+                    Ok(_) => Ok(stream),
+                    Err(e) => Err(Error::RingMasterIo(e)),
+                };
             }
+            Err(e) => {
+                if e.kind() == ErrorKind::TimedOut {
+                    result = Err(Error::RingMasterTimeout);
+                }
+            }
+        }
+    }
+    result
+}
+// Read one line of a port manager reply from the one in host/port:
+//
+fn read_portman_line(
+    reader: &mut BufReader<TcpStream>,
+    host: &str,
+    port: u16,
+) -> Result<String, Error> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(n) if n > 0 => Ok(line),
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            Err(Error::RingMasterTimeout)
+        }
+        _ => Err(Error::NoPortManager(format!("{}:{}", host, port))),
+    }
+}
+// Failing to connect to or losing the connection with the port manager
+// (at at) means it isn't there; other failures mean it's there but the
+// request failed:
+//
+fn portman_failure(e: portman_client::Error, at: &str) -> Error {
+    match e {
+        portman_client::Error::ConnectionFailed | portman_client::Error::ConnectionLost => {
+            Error::NoPortManager(String::from(at))
         }
+        e => Error::PortManError(e),
+    }
+}
+// Turn on TCP keepalive for a ringmaster connection if
+// set_ringmaster_keepalive asked for it.  Where we can, we give up
+// on the connection after 3 unanswered probes.  Unix sockets have no
+// keepalive (nor need it).
+//
+fn enable_keepalive(stream: &RingMasterStream) -> Result<(), Error> {
+    let ms = RINGMASTER_KEEPALIVE_MS.load(Ordering::SeqCst);
+    let stream = match stream {
+        RingMasterStream::Tcp(stream) if ms != 0 => stream,
+        _ => return Ok(()),
+    };
+    let interval = Duration::from_millis(ms);
+    let keepalive = socket2::TcpKeepalive::new().with_time(interval);
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+    let keepalive = keepalive.with_interval(interval).with_retries(3);
+    socket2::SockRef::from(stream)
+        .set_tcp_keepalive(&keepalive)
+        .map_err(Error::RingMasterIo)
+}
+// Send a request to the ringmaster:
+//
+fn send_request(stream: &mut RingMasterStream, request: &str) -> Result<(), Error> {
+    stream
+        .write_all(request.as_bytes())
+        .and_then(|_| stream.flush())
+        .map_err(Error::RingMasterIo)
+}
+// Read a reply line from the ringmaster one byte at a time so that
+// we don't consume any of the data that might follow it (a REMOTE
+// reply is followed by the ring data):
+//
+fn read_reply_line(stream: &mut RingMasterStream) -> Result<String, Error> {
+    let mut line = Vec::<u8>::new();
+    let mut byte = [0_u8; 1];
+    loop {
+        match stream.read(&mut byte) {
+            Ok(0) => {
+                return Err(Error::RingMasterIo(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "connection closed before a reply was received",
+                )))
+            }
+            Ok(_) => {
+                if byte[0] == b'\n' {
+                    break;
+                }
+                line.push(byte[0]);
+            }
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                    return Err(Error::RingMasterTimeout)
+                }
+                _ => return Err(Error::RingMasterIo(e)),
+            },
+        }
+    }
+    // The stream may be kept open for a long time so don't leave the
+    // reply timeout on it:
+
+    if let Err(e) = stream.set_read_timeout(None) {
+        return Err(Error::RingMasterIo(e));
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+// Do a timed read from a remote consumer's data stream.  A read timeout
+// maps to the same error a local consumer gives so callers can treat
+// both the same way.  A zero timeout is a non-blocking read.
+//
+fn remote_timed_get(
+    stream: &mut TcpStream,
+    data: &mut [u8],
+    timeout: Duration,
+) -> Result<usize, Error> {
+    let setup = if timeout == Duration::from_secs(0) {
+        stream.set_nonblocking(true)
+    } else {
+        stream
+            .set_nonblocking(false)
+            .and_then(|_| stream.set_read_timeout(Some(timeout)))
+    };
+    if let Err(e) = setup {
+        return Err(Error::RingMasterIo(e));
+    }
+    match stream.read(data) {
+        Ok(n) => Ok(n),
+        Err(e) => match e.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted => {
+                Err(Error::ConsumerError(consumer::Error::Timeout))
+            }
+            _ => Err(Error::RingMasterIo(e)),
+        },
+    }
+}
+///
+/// Whether name is usable as a ring name: a single file name with no
+/// directory part, so that it can't be used to get out of the ring buffer
+/// directory.  Empty names, ".", ".." and names containing a path
+/// separator are not.
+///
+pub fn is_simple_ring_name<N: AsRef<OsStr>>(name: N) -> bool {
+    let name = name.as_ref();
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(path::Component::Normal(first)), None) => first == name,
+        _ => false,
     }
 }
 //
 // Take a full path to a ring buffer file and return just the filename (ring name)
-// as that's what the ringmaster needs to see.
+// as that's what the ringmaster needs to see.  Paths without a final
+// name (e.g. ending in ..) or whose name is not UTF-8 give a MapError.
 //
-fn ring_name(filename: &str) -> String {
-    String::from(
-        path::Path::new(filename)
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap(),
-    )
+fn ring_name(filename: &Path) -> Result<String, Error> {
+    match filename.file_name() {
+        Some(name) => match name.to_str() {
+            Some(s) => Ok(String::from(s)),
+            None => Err(Error::MapError(format!(
+                "{} - the ring name is not valid UTF-8",
+                filename.display()
+            ))),
+        },
+        None => Err(Error::MapError(format!(
+            "{} does not end in a ring buffer name",
+            filename.display()
+        ))),
+    }
 }
 //
 // Tell the ring master we're connecting a consumer.
 // This formats the CONNECT message, uses ringmaster_request
 // for the rest of it.
 //
-fn connect_consumer(port: u16, ring: &str, slot: u32) -> Result<TcpStream, Error> {
+fn connect_consumer(
+    transport: &Transport,
+    ring: &str,
+    slot: u32,
+) -> Result<RingMasterStream, Error> {
     let request = format!(
         "CONNECT {{{}}} consumer.{} {} RUST Client\n",
         ring,
         slot,
         process::id()
     );
-    ringmaster_request(port, &request)
+    let stream = ringmaster_request(transport, &request)?;
+    enable_keepalive(&stream)?;
+    Ok(stream)
 }
 // Tell the ring master we're connecting a producer.
 // Formats the message and lets ringmaster_request do the rest:
 //
-fn connect_producer(port: u16, ring: &str) -> Result<TcpStream, Error> {
+fn connect_producer(transport: &Transport, ring: &str) -> Result<RingMasterStream, Error> {
     let request = format!(
         "CONNECT {{{}}} producer {} RUST Client\n",
         ring,
         process::id()
     );
-
-    ringmaster_request(port, &request)
+    let stream = ringmaster_request(transport, &request)?;
+    enable_keepalive(&stream)?;
+    Ok(stream)
 }
 
+// Resolve the ringmaster host into the addresses we can try to
+// connect to on port.  By default that's the loopback address,
+// IPv4 first, then IPv6 for ringmasters that only listen on ::1.
+//
+fn ringmaster_addresses(port: u16) -> Result<Vec<SocketAddr>, Error> {
+    match RINGMASTER_HOST.lock().unwrap().clone() {
+        Some(host) => resolve(&host, port),
+        None => Ok(vec![
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            SocketAddr::from((Ipv6Addr::LOCALHOST, port)),
+        ]),
+    }
+}
+// Resolve a host/port into socket addresses.  It's an error if there
+// are none.
+//
+fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>, Error> {
+    match (host, port).to_socket_addrs() {
+        Ok(addresses) => {
+            let addresses: Vec<SocketAddr> = addresses.collect();
+            if addresses.is_empty() {
+                Err(Error::BadHost(String::from(host)))
+            } else {
+                Ok(addresses)
+            }
+        }
+        Err(_) => Err(Error::BadHost(String::from(host))),
+    }
+}
+// Connect to the first ringmaster address that accepts us within
+// the ringmaster timeout.  The read timeout on the resulting stream
+// is set so that waiting for replies is time limited too.
+// If no address accepts us, there's no ringmaster unless some
+// attempt timed out.
+//
+fn connect_ringmaster(addresses: &[SocketAddr]) -> Result<TcpStream, Error> {
+    let timeout = Duration::from_millis(RINGMASTER_TIMEOUT_MS.load(Ordering::SeqCst));
+    let mut result = Err(Error::NoRingMaster);
+    for address in addresses {
+        match TcpStream::connect_timeout(address, timeout) {
+            Ok(stream) => {
+                debug!("Connected to the ringmaster at {}", address);
+                return match stream.set_read_timeout(Some(timeout)) {
+                    Ok(_) => Ok(stream),
+                    Err(e) => Err(Error::RingMasterIo(e)),
+                };
+            }
+            Err(e) => {
+                if e.kind() == ErrorKind::TimedOut {
+                    result = Err(Error::RingMasterTimeout);
+                }
+            }
+        }
+    }
+    result
+}
+// Connect to a ringmaster listening on a Unix socket.  Like
+// connect_ringmaster we set the read timeout; a socket nobody listens
+// on means there's no ringmaster.
+//
+#[cfg(unix)]
+fn connect_unix_ringmaster(socket: &RingMasterSocket) -> Result<RingMasterStream, Error> {
+    let stream = match socket {
+        RingMasterSocket::Path(path) => UnixStream::connect(path),
+        #[cfg(target_os = "linux")]
+        RingMasterSocket::Abstract(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())
+                .and_then(|address| UnixStream::connect_addr(&address))
+        }
+        #[cfg(not(target_os = "linux"))]
+        RingMasterSocket::Abstract(_) => Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "abstract Unix sockets are only supported on Linux",
+        )),
+    };
+    let stream = match stream {
+        Ok(stream) => stream,
+        Err(e) => {
+            return match e.kind() {
+                ErrorKind::NotFound | ErrorKind::ConnectionRefused => Err(Error::NoRingMaster),
+                _ => Err(Error::RingMasterIo(e)),
+            }
+        }
+    };
+    debug!("Connected to the ringmaster at {}", socket);
+    let timeout = Duration::from_millis(RINGMASTER_TIMEOUT_MS.load(Ordering::SeqCst));
+    match stream.set_read_timeout(Some(timeout)) {
+        Ok(_) => Ok(RingMasterStream::Unix(stream)),
+        Err(e) => Err(Error::RingMasterIo(e)),
+    }
+}
+#[cfg(not(unix))]
+fn connect_unix_ringmaster(_socket: &RingMasterSocket) -> Result<RingMasterStream, Error> {
+    Err(Error::RingMasterIo(io::Error::new(
+        ErrorKind::Unsupported,
+        "Unix sockets are not supported here",
+    )))
+}
 // Does a ring master request and analyzes the result.
+// Only failing to connect means there's no ringmaster; once connected,
+// I/O failures are RingMasterIo errors and a reply other than OK is
+// turned into an error by reply_error.  The request and reply are the
+// same whichever transport reaches the ringmaster.
 
-fn ringmaster_request(port: u16, request: &str) -> Result<TcpStream, Error> {
-    match TcpStream::connect(format!("127.0.0.1:{}", port).as_str()) {
-        Err(_) => Err(Error::NoRingMaster),
-        Ok(mut stream) => {
-            // write the request and use a buffered reader to get the reply line.
-            // we can do this since while we need to keep the stream open we're not
-            // interacting any more.
+fn ringmaster_request(transport: &Transport, request: &str) -> Result<RingMasterStream, Error> {
+    let mut stream = match transport {
+        Transport::Tcp(port) => {
+            RingMasterStream::Tcp(connect_ringmaster(&ringmaster_addresses(*port)?)?)
+        }
+        Transport::Unix(socket) => connect_unix_ringmaster(socket)?,
+    };
 
-            if let Err(_) = stream.write_all(request.as_bytes()) {
-                Err(Error::NoRingMaster)
-            } else {
-                if let Err(_) = stream.flush() {
-                    Err(Error::NoRingMaster)
-                } else {
-                    let mut reader = BufReader::new(stream.try_clone().unwrap());
-                    let mut line = String::new();
-                    if let Ok(_n) = reader.read_line(&mut line) {
-                        if line.trim() == "OK" {
-                            Ok(stream)
-                        } else {
-                            Err(Error::RingMasterFail(line))
-                        }
-                    } else {
-                        Err(Error::NoRingMaster)
+    // We need to keep the stream open so the reply is read
+    // directly from the stream rather than a reader that owns it.
+
+    send_request(&mut stream, request)?;
+    let line = read_reply_line(&mut stream)?;
+    if line.trim() == "OK" {
+        Ok(stream)
+    } else {
+        Err(reply_error(line))
+    }
+}
+// Split a Tcl list into its elements.  Elements are separated by white
+// space; braces group (and are stripped from) an element, quotes too.
+// Backslash escapes are kept as they are, which is fine for ring names
+// and numbers.
+//
+fn tcl_list(text: &str) -> Vec<String> {
+    let mut elements = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let first = match chars.next() {
+            Some(c) => c,
+            None => break,
+        };
+        let mut element = String::new();
+        match first {
+            '{' => {
+                let mut depth = 1;
+                for c in chars.by_ref() {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => {}
                     }
+                    if depth == 0 {
+                        break;
+                    }
+                    element.push(c);
+                }
+            }
+            '"' => {
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                    element.push(c);
+                }
+            }
+            c => {
+                element.push(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    element.push(c);
                 }
             }
         }
+        elements.push(element);
+    }
+    elements
+}
+// Make sense of one {name usage} pair from a LIST reply:
+//
+fn listed_ring(pair: &str) -> Option<ListedRing> {
+    let pair = tcl_list(pair);
+    let name = pair.first()?.clone();
+    let usage = pair.get(1).map(|u| tcl_list(u)).unwrap_or_default();
+    Some(ListedRing {
+        name,
+        data_bytes: usage.first().and_then(|s| s.parse().ok()),
+        producer: usage.get(3).and_then(|s| s.parse::<u32>().ok()),
+        consumers: usage.get(6).map(|c| tcl_list(c).len()).unwrap_or(0),
+    })
+}
+// The ringmaster's replies are single lines (a LIST's OK is followed by
+// one more, see list_ringmaster_rings):
+//
+//    reply  := "OK" [" BINARY FOLLOWS"] | "ERROR" [" " reason]
+//
+// where reason is free text for people.  The few reasons we act on are
+// matched whole, as NSCLDAQ's ringmaster (RingMaster.tcl, its CONNECT
+// handler) sends them, with {} standing for the ring name it fills in:
+//
+// *  "Ring {} does not exist": RingMasterNoRing.
+// *  "Connect must be from localhost", a CONNECT from another host:
+//    RingMasterDenied.
+//
+// Anything else, including replies that don't fit the grammar, is a
+// RingMasterFail with the line as it came.
+//
+type ReasonError = fn(String) -> Error;
+const KNOWN_REASONS: &[(&str, ReasonError)] = &[
+    ("Ring {} does not exist", Error::RingMasterNoRing),
+    ("Connect must be from localhost", Error::RingMasterDenied),
+];
+
+fn reply_error(line: String) -> Error {
+    let reason = match line.trim().strip_prefix("ERROR ") {
+        Some(reason) => reason,
+        None => return Error::RingMasterFail(line),
+    };
+    let known = KNOWN_REASONS
+        .iter()
+        .find(|(pattern, _)| reason_matches(pattern, reason));
+    match known {
+        Some((_, error)) => error(line),
+        None => Error::RingMasterFail(line),
+    }
+}
+// Whether reason is pattern with its {} (if it has one) filled in:
+//
+fn reason_matches(pattern: &str, reason: &str) -> bool {
+    match pattern.split_once("{}") {
+        None => reason == pattern,
+        Some((before, after)) => reason
+            .strip_prefix(before)
+            .and_then(|rest| rest.strip_suffix(after))
+            .is_some_and(|name| !name.is_empty()),
     }
 }
@@ -1,2 +1,4 @@
 mod client;
+mod rings;
 pub use self::client::*;
+pub use self::rings::*;
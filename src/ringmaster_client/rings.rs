@@ -0,0 +1,121 @@
+//!
+//! The ringmaster_client::rings module looks at the ring buffers in a
+//! directory without becoming a client of them.  This lets users see
+//! which rings there are and what state they're in.
+//!
+
+use nscldaq_ringbuffer::ringbuffer::{RingBufferMap, UNUSED_ENTRY};
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+///
+/// The magic string at the start of the header of the ring buffers we
+/// understand.  The header has no version number so this is all that
+/// identifies the format.
+///
+pub const RING_MAGIC: &str = "NSCLRing";
+
+///
+/// What we can tell about a ring buffer from its header:
+///
+/// *  name - the ring name (its file name).
+/// *  data_bytes - the size of its data area.
+/// *  producer - the producer's pid if there is one.
+/// *  consumers - the number of consumers attached.
+/// *  max_consumers - the number of consumer slots.
+///
+#[derive(Debug, Clone)]
+pub struct RingInfo {
+    pub name: String,
+    pub data_bytes: usize,
+    pub producer: Option<u32>,
+    pub consumers: usize,
+    pub max_consumers: usize,
+}
+
+///
+/// Describe the ring buffer in ring_file.  The file is mapped but
+/// nothing in it is modified.  If it's not a ring buffer the map
+/// error's message is returned; as for attach_consumer, only UTF-8 paths
+/// can be mapped.
+///
+pub fn ring_info<P: AsRef<Path>>(ring_file: P) -> Result<RingInfo, String> {
+    let ring_file = ring_file.as_ref();
+    check_ring_format(ring_file)?;
+    let map_path = ring_file
+        .to_str()
+        .ok_or_else(|| format!("{} is not a valid UTF-8 path", ring_file.display()))?;
+    let mut map = RingBufferMap::new(map_path)?;
+    let usage = map.get_usage();
+    let name = ring_file
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| ring_file.display().to_string());
+    Ok(RingInfo {
+        name,
+        data_bytes: map.data_bytes(),
+        producer: if usage.producer_pid == UNUSED_ENTRY {
+            None
+        } else {
+            Some(usage.producer_pid)
+        },
+        consumers: usage.consumer_usage.len(),
+        max_consumers: map.max_consumers(),
+    })
+}
+
+///
+/// Check that ring_file starts with a ring buffer header in the format
+/// we understand (see RING_MAGIC) before it's mapped, so a ring made by
+/// an incompatible version (or some other file) is reported as such,
+/// saying what was expected and what was found, rather than being read as
+/// garbage.  A file that can't be opened or is too short to have a header
+/// is left for mapping it to fail on.
+///
+pub fn check_ring_format<P: AsRef<Path>>(ring_file: P) -> Result<(), String> {
+    let ring_file = ring_file.as_ref();
+    let mut magic = [0u8; 32];
+    let read = fs::File::open(ring_file).and_then(|mut f| f.read_exact(&mut magic));
+    if read.is_err() {
+        return Ok(());
+    }
+    let found = magic.split(|&b| b == 0).next().unwrap_or(&[]);
+    let found = String::from_utf8_lossy(found);
+    if found.trim() == RING_MAGIC {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} is not a ring buffer in a format we understand: expected the magic string {:?} but found {:?}",
+            ring_file.display(),
+            RING_MAGIC,
+            found.trim()
+        ))
+    }
+}
+
+///
+/// Describe all the ring buffers in directory, sorted by name.  Files
+/// that aren't ring buffers (and subdirectories etc.) are skipped; how
+/// many were skipped is returned along with the rings.
+///
+pub fn list_rings<P: AsRef<Path>>(directory: P) -> io::Result<(Vec<RingInfo>, usize)> {
+    let mut rings = Vec::<RingInfo>::new();
+    let mut skipped = 0;
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let is_file = entry.file_type().map(|t| t.is_file()).unwrap_or(false);
+        let info = if is_file {
+            ring_info(entry.path()).ok()
+        } else {
+            None
+        };
+        match info {
+            Some(info) => rings.push(info),
+            None => skipped += 1,
+        }
+    }
+    rings.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok((rings, skipped))
+}
@@ -0,0 +1,131 @@
+//!
+//! Tests of ring2stdout's checking of its command line arguments.
+//! Bad arguments exit with status 1 and say what's wrong.
+//!
+
+use std::process::Command;
+
+// Run ring2stdout with args, returning its exit status and stderr:
+
+fn run(args: &[&str]) -> (Option<i32>, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_ring2stdout"))
+        .args(args)
+        .env_remove("RINGTOSTDOUT_DIR")
+        .env_remove("RINGTOSTDOUT_RING")
+        .output()
+        .unwrap();
+    (
+        output.status.code(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+fn run_port(port: &str) -> (Option<i32>, String) {
+    run(&["--directory", "/tmp", "--ring", "ring", "--port", port])
+}
+
+#[test]
+fn port_not_a_number() {
+    let (status, stderr) = run_port("thirty");
+    assert_eq!(status, Some(1));
+    assert!(stderr.contains("must be an unsigned integer"), "{}", stderr);
+}
+
+#[test]
+fn port_out_of_range() {
+    let (status, stderr) = run_port("70000");
+    assert_eq!(status, Some(1));
+    assert!(stderr.contains("out of range"), "{}", stderr);
+    assert!(stderr.contains("1-65535"), "{}", stderr);
+}
+
+#[test]
+fn port_zero() {
+    let (status, stderr) = run_port("0");
+    assert_eq!(status, Some(1));
+    assert!(stderr.contains("can't be 0"), "{}", stderr);
+}
+
+#[test]
+fn log_file_gets_the_diagnostics() {
+    let log = std::env::temp_dir().join(format!("ring2stdout_log_{}", std::process::id()));
+    let (status, stderr) = run(&[
+        "--log-file",
+        log.to_str().unwrap(),
+        "--directory",
+        "/no/such/directory",
+        "--ring",
+        "ring",
+    ]);
+    let logged = std::fs::read_to_string(&log).unwrap();
+    std::fs::remove_file(&log).unwrap();
+    assert_eq!(status, Some(1));
+    assert_eq!(stderr, "");
+    assert!(logged.contains("does not exist"), "{}", logged);
+}
+
+#[test]
+fn log_file_unopenable() {
+    let (status, stderr) = run(&["--log-file", "/no/such/directory/log", "--ring", "ring"]);
+    assert_eq!(status, Some(1));
+    assert!(stderr.contains("Unable to open the log file"), "{}", stderr);
+}
+
+#[test]
+fn no_directory_check_defers_to_attaching() {
+    let args = [
+        "--directory",
+        "/no/such/directory",
+        "--ring",
+        "ring",
+        "--port",
+        "1",
+    ];
+    let (status, stderr) = run(&args);
+    assert_eq!(status, Some(1));
+    assert!(stderr.contains("does not exist"), "{}", stderr);
+
+    let (status, stderr) = run(&[&args[..], &["--no-directory-check"]].concat());
+    assert_eq!(status, Some(2));
+    assert!(stderr.contains("Failed to attach"), "{}", stderr);
+}
+
+#[test]
+fn stderr_prefix() {
+    let args = ["--directory", "/no/such/directory", "--ring", "ring"];
+    let (_, stderr) = run(&args);
+    assert!(stderr.starts_with("ring: ERROR: "), "{}", stderr);
+
+    let (_, stderr) = run(&[&args[..], &["--stderr-prefix", "east"]].concat());
+    assert!(stderr.starts_with("east: ERROR: "), "{}", stderr);
+
+    let (_, stderr) = run(&[&args[..], &["--stderr-prefix", ""]].concat());
+    assert!(stderr.starts_with("ERROR: "), "{}", stderr);
+}
+
+#[test]
+fn buffer_count_at_least_two() {
+    let (status, stderr) = run(&[
+        "--directory",
+        "/tmp",
+        "--ring",
+        "ring",
+        "--buffer-count",
+        "1",
+    ]);
+    assert_eq!(status, Some(1));
+    assert!(stderr.contains("at least 2"), "{}", stderr);
+
+    let (status, stderr) = run(&[
+        "--directory",
+        "/tmp",
+        "--ring",
+        "ring",
+        "--buffer-count",
+        "3",
+        "--queue-depth",
+        "2",
+    ]);
+    assert_eq!(status, Some(1));
+    assert!(stderr.contains("cannot be used with"), "{}", stderr);
+}
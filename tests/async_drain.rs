@@ -0,0 +1,59 @@
+//!
+//! Test of drain::run_to_writer_async (async feature): a producer puts
+//! data in a ring while the async runner forwards it to a Vec.
+//!
+#![cfg(feature = "async")]
+
+mod common;
+
+use common::*;
+use nscldaq_ringtostdout::drain;
+use nscldaq_ringtostdout::ringmaster_client::{self, ClientType};
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn forwards_to_async_writer() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ring = make_ring("async_drain");
+    let ring_file = String::from(ring.to_str().unwrap());
+
+    let producer_master = MockRingMaster::start(Reply::Line("OK\n"));
+    use_mocks(&producer_master);
+    let mut producer = ringmaster_client::attach_producer(&ring_file).unwrap();
+
+    // The consumer only sees data put after it attaches, so keep putting
+    // until it has had enough:
+
+    let consumer_master = MockRingMaster::start(Reply::Line("OK\n"));
+    use_mocks(&consumer_master);
+    let putter = thread::spawn(move || {
+        if let ClientType::Producer(p) = &mut producer.client {
+            for _ in 0..50 {
+                p.blocking_put(b"0123456789").unwrap();
+                thread::sleep(Duration::from_millis(20));
+            }
+        }
+    });
+
+    let mut options = drain::Options::new();
+    options.max_bytes = Some(50);
+    options.checksum = true;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let mut output = Vec::<u8>::new();
+    let stats = runtime
+        .block_on(drain::run_to_writer_async(&ring_file, options, &mut output))
+        .unwrap();
+
+    assert_eq!(
+        output,
+        b"01234567890123456789012345678901234567890123456789"
+    );
+    assert_eq!(stats.bytes, 50);
+    assert_eq!(stats.checksum.unwrap().bytes, 50);
+    putter.join().unwrap();
+    fs::remove_file(ring).unwrap();
+}
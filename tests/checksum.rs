@@ -0,0 +1,71 @@
+//!
+//! Tests of the drain checksum using a remote consumer of a mock
+//! ringmaster as the data source.
+//!
+
+mod common;
+
+use common::*;
+use nscldaq_ringtostdout::drain;
+use nscldaq_ringtostdout::framing;
+use nscldaq_ringtostdout::ringmaster_client;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+// Drain the payload sent by a mock ringmaster to a Vec:
+
+fn drain_payload(payload: &'static [u8], frame: bool) -> (Vec<u8>, drain::Stats) {
+    let ringmaster = MockRingMaster::start(Reply::LineThenData("OK BINARY FOLLOWS\n", payload));
+    use_mocks(&ringmaster);
+    let mut rings = vec![ringmaster_client::attach_remote_consumer("127.0.0.1", "fox").unwrap()];
+
+    let mut options = drain::Options::new();
+    options.checksum = true;
+    options.frame = frame;
+    options.timeout = Duration::from_millis(100);
+    let mut output = Vec::<u8>::new();
+    let (reason, stats) = drain::drain(&mut rings, &mut output, &options, &AtomicBool::new(false));
+    assert!(matches!(reason, drain::StopReason::EndOfData));
+    (output, stats)
+}
+
+#[test]
+fn known_payload() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let (output, stats) = drain_payload(b"123456789", false);
+
+    // 0xcbf43926 is the standard CRC32 check value for "123456789":
+
+    assert_eq!(output, b"123456789");
+    let checksum = stats.checksum.unwrap();
+    assert_eq!(checksum.bytes, 9);
+    assert_eq!(checksum.crc32(), 0xcbf43926);
+}
+
+#[test]
+fn covers_frame_headers() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let (output, stats) = drain_payload(b"123456789", true);
+
+    let checksum = stats.checksum.unwrap();
+    assert_eq!(checksum.bytes, output.len() as u64);
+    assert_eq!(checksum.bytes, 9 + framing::FRAME_HEADER_SIZE as u64);
+    assert_eq!(checksum.crc32(), crc32fast::hash(&output));
+}
+
+#[test]
+fn off_by_default() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ringmaster = MockRingMaster::start(Reply::LineThenData("OK BINARY FOLLOWS\n", b"data"));
+    use_mocks(&ringmaster);
+    let mut rings = vec![ringmaster_client::attach_remote_consumer("127.0.0.1", "fox").unwrap()];
+
+    let (_, stats) = drain::drain(
+        &mut rings,
+        Vec::<u8>::new(),
+        &drain::Options::new(),
+        &AtomicBool::new(false),
+    );
+    assert!(stats.checksum.is_none());
+    assert_eq!(stats.bytes, 4);
+}
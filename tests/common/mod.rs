@@ -0,0 +1,165 @@
+//!
+//! Mock port manager and ringmaster servers and a ring buffer file
+//! maker shared by the integration tests.
+//!
+//! The port manager port and ringmaster host are process wide settings
+//! so tests that use the mocks serialize themselves with SERIAL.
+//!
+#![allow(dead_code)] // Not every test uses every helper.
+
+use nscldaq_ringtostdout::ringmaster_client;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+pub static SERIAL: Mutex<()> = Mutex::new(());
+
+// How the mock ringmaster responds to a request:
+
+#[derive(Clone)]
+pub enum Reply {
+    Line(&'static str),                        // Send this reply.
+    LineThenData(&'static str, &'static [u8]), // Reply then binary data.
+    Nothing,                                   // Hold the connection but never reply.
+    Close,                                     // Close without replying.
+}
+
+// A mock ringmaster.  It accepts one connection, reads the request
+// line, sends it to the test via the channel and responds as told.
+
+pub struct MockRingMaster {
+    pub port: u16,
+    requests: mpsc::Receiver<String>,
+}
+
+impl MockRingMaster {
+    pub fn start(reply: Reply) -> MockRingMaster {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            sender.send(request).unwrap();
+            let mut stream = stream;
+            match reply {
+                Reply::Line(line) => {
+                    stream.write_all(line.as_bytes()).unwrap();
+                    hold(stream);
+                }
+                Reply::LineThenData(line, data) => {
+                    stream.write_all(line.as_bytes()).unwrap();
+                    stream.write_all(data).unwrap();
+                }
+                Reply::Nothing => hold(stream),
+                Reply::Close => {}
+            }
+        });
+        MockRingMaster { port, requests }
+    }
+    pub fn request(&self) -> String {
+        self.requests.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+}
+// Keep a connection open until the client closes it:
+
+fn hold(mut stream: TcpStream) {
+    let mut buffer = [0_u8; 100];
+    while let Ok(n) = stream.read(&mut buffer) {
+        if n == 0 {
+            break;
+        }
+    }
+}
+
+// A mock port manager that answers LIST requests advertising a
+// RingMaster on ringmaster_port.  Returns the port it listens on.
+
+pub fn start_port_manager(ringmaster_port: u16) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || serve_port_manager(listener, ringmaster_port));
+    port
+}
+
+// The same but it only starts listening (on port) after delay, like a
+// port manager that's still starting up:
+
+pub fn start_port_manager_later(port: u16, ringmaster_port: u16, delay: Duration) {
+    thread::spawn(move || {
+        thread::sleep(delay);
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        serve_port_manager(listener, ringmaster_port);
+    });
+}
+
+fn serve_port_manager(listener: TcpListener, ringmaster_port: u16) {
+    for stream in listener.incoming() {
+        let stream = stream.unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap_or(0) > 0 {
+            let reply = format!("OK 2\n1234 Other fox\n{} RingMaster fox\n", ringmaster_port);
+            writer.write_all(reply.as_bytes()).unwrap();
+            line.clear();
+        }
+    }
+}
+
+// Make a ring buffer file with the layout nscldaq_ringbuffer expects.
+// There's no producer and all consumer slots are free.
+
+pub fn make_ring(name: &str) -> PathBuf {
+    let max_consumers: usize = 4;
+    let data_bytes: usize = 4096;
+    let word = std::mem::size_of::<usize>();
+    let producer_offset = 32 + 6 * word;
+    let slot_size = 2 * word; // offset and pid padded to alignment.
+    let consumer_offset = producer_offset + slot_size;
+    let data_offset = consumer_offset + max_consumers * slot_size;
+    let top_offset = data_offset + data_bytes - 1;
+
+    let mut contents = Vec::<u8>::new();
+    let mut magic = [0_u8; 32];
+    magic[0..8].copy_from_slice(b"NSCLRing");
+    contents.extend_from_slice(&magic);
+    for field in &[
+        max_consumers,
+        data_bytes,
+        producer_offset,
+        consumer_offset,
+        data_offset,
+        top_offset,
+    ] {
+        contents.extend_from_slice(&field.to_ne_bytes());
+    }
+    for _ in 0..max_consumers + 1 {
+        let mut slot = vec![0_u8; slot_size];
+        slot[0..word].copy_from_slice(&data_offset.to_ne_bytes());
+        slot[word..word + 4].copy_from_slice(&0xffffffff_u32.to_ne_bytes());
+        contents.extend_from_slice(&slot);
+    }
+    contents.resize(top_offset + 1, 0);
+
+    let mut path = std::env::temp_dir();
+    path.push(format!("{}_{}", name, process::id()));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+// Point the client at the mocks:
+
+pub fn use_mocks(ringmaster: &MockRingMaster) {
+    ringmaster_client::set_portman_port(start_port_manager(ringmaster.port));
+    ringmaster_client::set_ringmaster_host("127.0.0.1");
+    ringmaster_client::set_ringmaster_timeout(Duration::from_secs(5));
+}
@@ -0,0 +1,552 @@
+//!
+//! Tests of the drain loop using MockReader, a RingReader that plays
+//! back a scripted sequence of get results.
+//!
+
+use nscldaq_ringbuffer::ringbuffer::consumer;
+use nscldaq_ringtostdout::drain;
+use nscldaq_ringtostdout::framing;
+use nscldaq_ringtostdout::ringmaster_client::{Error, RingReader};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+// What a get returns:
+
+enum Step {
+    Data(&'static [u8]),
+    Timeout,
+    Retryable,
+    Fatal,
+}
+
+// Plays back its steps; once they're used up the data source has
+// gone away (Ok(0)).  The timeout it was last asked to wait is kept.
+
+struct MockReader {
+    steps: VecDeque<Step>,
+    gets: usize,
+    timeout: Option<Duration>,
+}
+impl MockReader {
+    fn new(steps: Vec<Step>) -> MockReader {
+        MockReader {
+            steps: steps.into(),
+            gets: 0,
+            timeout: None,
+        }
+    }
+}
+impl RingReader for MockReader {
+    fn timed_get(&mut self, data: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        self.gets += 1;
+        self.timeout = Some(timeout);
+        match self.steps.pop_front() {
+            None => Ok(0),
+            Some(Step::Data(bytes)) => {
+                // Like a real ring, a get can return less than is there;
+                // put the rest back for the next get:
+
+                let n = std::cmp::min(data.len(), bytes.len());
+                data[0..n].copy_from_slice(&bytes[0..n]);
+                if n < bytes.len() {
+                    self.steps.push_front(Step::Data(&bytes[n..]));
+                }
+                Ok(n)
+            }
+            Some(Step::Timeout) => Err(Error::ConsumerError(consumer::Error::Timeout)),
+            Some(Step::Retryable) => Err(Error::RingMasterIo(io::Error::from(
+                io::ErrorKind::Interrupted,
+            ))),
+            Some(Step::Fatal) => Err(Error::ConsumerError(consumer::Error::TooMuchData)),
+        }
+    }
+}
+
+// Drain the readers into a Vec:
+
+fn run(
+    readers: &mut [MockReader],
+    options: &drain::Options,
+) -> (drain::StopReason, drain::Stats, Vec<u8>) {
+    let mut output = Vec::<u8>::new();
+    let (reason, stats) = drain::drain(readers, &mut output, options, &AtomicBool::new(false));
+    (reason, stats, output)
+}
+
+#[test]
+fn writes_exactly_the_data() {
+    let mut readers = [MockReader::new(vec![
+        Step::Data(b"hello "),
+        Step::Data(b"world"),
+    ])];
+    let (reason, stats, output) = run(&mut readers, &drain::Options::new());
+    assert!(matches!(reason, drain::StopReason::EndOfData));
+    assert_eq!(output, b"hello world");
+    assert_eq!(stats.bytes, 11);
+    assert_eq!(stats.gets, 2);
+}
+
+// The buffer is reused; a short get after a long one must not write the
+// stale tail of the long one:
+
+#[test]
+fn short_get_writes_only_its_bytes() {
+    let mut readers = [MockReader::new(vec![
+        Step::Data(b"long get"),
+        Step::Data(b"ab"),
+        Step::Timeout,
+        Step::Data(b"c"),
+    ])];
+    let (_, stats, output) = run(&mut readers, &drain::Options::new());
+    assert_eq!(output, b"long getabc");
+    assert_eq!(stats.bytes, 11);
+}
+
+#[test]
+fn bulk_gets_wait_longer() {
+    let mut options = drain::Options::new();
+    options.bulk = true;
+    let mut readers = [MockReader::new(vec![Step::Data(b"bulk")])];
+    let (_, _, output) = run(&mut readers, &options);
+    assert_eq!(output, b"bulk");
+    assert_eq!(readers[0].timeout, Some(drain::BULK_TIMEOUT));
+
+    let mut readers = [MockReader::new(vec![Step::Data(b"quick")])];
+    run(&mut readers, &drain::Options::new());
+    assert_eq!(readers[0].timeout, Some(Duration::from_millis(1)));
+}
+
+#[test]
+fn timeouts_continue() {
+    let mut readers = [MockReader::new(vec![
+        Step::Timeout,
+        Step::Data(b"one"),
+        Step::Timeout,
+        Step::Timeout,
+        Step::Data(b"two"),
+    ])];
+    let (reason, stats, output) = run(&mut readers, &drain::Options::new());
+    assert!(matches!(reason, drain::StopReason::EndOfData));
+    assert_eq!(output, b"onetwo");
+    assert_eq!(stats.gets, 2);
+    assert_eq!(stats.timeouts, 3);
+}
+
+#[test]
+fn fatal_error_stops() {
+    let mut readers = [MockReader::new(vec![
+        Step::Data(b"before"),
+        Step::Fatal,
+        Step::Data(b"after"),
+    ])];
+    let (reason, _, output) = run(&mut readers, &drain::Options::new());
+    assert!(matches!(
+        reason,
+        drain::StopReason::RingError(Error::ConsumerError(consumer::Error::TooMuchData))
+    ));
+    assert_eq!(output, b"before"); // Flushed even though we failed.
+}
+
+#[test]
+fn retryable_errors_up_to_limit() {
+    let mut options = drain::Options::new();
+    options.retry_limit = 2;
+
+    let mut readers = [MockReader::new(vec![
+        Step::Retryable,
+        Step::Retryable,
+        Step::Data(b"ok"),
+    ])];
+    let (reason, _, output) = run(&mut readers, &options);
+    assert!(matches!(reason, drain::StopReason::EndOfData));
+    assert_eq!(output, b"ok");
+
+    let mut readers = [MockReader::new(vec![
+        Step::Retryable,
+        Step::Retryable,
+        Step::Retryable,
+        Step::Data(b"never"),
+    ])];
+    let (reason, _, output) = run(&mut readers, &options);
+    assert!(matches!(
+        reason,
+        drain::StopReason::RingError(Error::RingMasterIo(_))
+    ));
+    assert!(output.is_empty());
+
+    // Timeouts between the errors don't start the count again:
+
+    let mut readers = [MockReader::new(vec![
+        Step::Retryable,
+        Step::Timeout,
+        Step::Retryable,
+        Step::Timeout,
+        Step::Retryable,
+        Step::Data(b"never"),
+    ])];
+    let (reason, _, output) = run(&mut readers, &options);
+    assert!(matches!(
+        reason,
+        drain::StopReason::RingError(Error::RingMasterIo(_))
+    ));
+    assert!(output.is_empty());
+}
+
+#[test]
+fn max_bytes_trims_gets() {
+    let mut options = drain::Options::new();
+    options.max_bytes = Some(7);
+
+    let mut readers = [MockReader::new(vec![
+        Step::Data(b"0123"),
+        Step::Data(b"456789"),
+    ])];
+    let (reason, stats, output) = run(&mut readers, &options);
+    assert!(matches!(reason, drain::StopReason::LimitReached));
+    assert_eq!(output, b"0123456");
+    assert_eq!(stats.bytes, 7);
+}
+
+#[test]
+fn several_rings_are_framed() {
+    let mut readers = [
+        MockReader::new(vec![Step::Data(b"a1"), Step::Data(b"a2")]),
+        MockReader::new(vec![Step::Timeout, Step::Data(b"b1")]),
+    ];
+    let (reason, _, output) = run(&mut readers, &drain::Options::new());
+    assert!(matches!(reason, drain::StopReason::EndOfData));
+
+    let mut frames = Vec::<(u32, Vec<u8>)>::new();
+    let mut input = output.as_slice();
+    while let Some((header, payload)) = framing::read_frame(&mut input).unwrap() {
+        frames.push((header.ring_index, payload));
+    }
+    assert_eq!(
+        frames,
+        vec![
+            (0, b"a1".to_vec()),
+            (0, b"a2".to_vec()),
+            (1, b"b1".to_vec())
+        ]
+    );
+}
+
+// A frame claiming more data than the stream has is an error, however
+// much it claims:
+
+#[test]
+fn short_frame_is_unexpected_eof() {
+    let mut input = framing::encode_header(0, u32::MAX).to_vec();
+    input.extend_from_slice(b"short");
+    let error = framing::read_frame(&mut input.as_slice()).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn shutdown_stops_before_reading() {
+    let mut readers = [MockReader::new(vec![Step::Data(b"data")])];
+    let mut output = Vec::<u8>::new();
+    let (reason, _) = drain::drain(
+        &mut readers,
+        &mut output,
+        &drain::Options::new(),
+        &AtomicBool::new(true),
+    );
+    assert!(matches!(reason, drain::StopReason::Shutdown));
+    assert_eq!(readers[0].gets, 0);
+    assert!(output.is_empty());
+}
+
+#[test]
+fn boxed_readers_mix() {
+    let mut readers: Vec<Box<dyn RingReader>> =
+        vec![Box::new(MockReader::new(vec![Step::Data(b"boxed")]))];
+    let mut options = drain::Options::new();
+    options.checksum = true;
+    let mut output = Vec::<u8>::new();
+    let (_, stats) = drain::drain(&mut readers, &mut output, &options, &AtomicBool::new(false));
+    assert_eq!(output, b"boxed");
+    assert_eq!(stats.checksum.unwrap().bytes, 5);
+}
+
+// Ring items: a 12 byte item followed by a 10 byte item, split across
+// gets in awkward places (including inside the second header).
+
+#[test]
+fn item_limit_writes_whole_items() {
+    let mut items = Vec::<u8>::new();
+    for (size, fill) in &[(12_u32, b'a'), (10_u32, b'b'), (9_u32, b'c')] {
+        items.extend_from_slice(&size.to_ne_bytes());
+        items.extend_from_slice(&1_u32.to_ne_bytes());
+        items.resize(items.len() + *size as usize - 8, *fill);
+    }
+    let items: &'static [u8] = Box::leak(items.into_boxed_slice());
+
+    let mut options = drain::Options::new();
+    options.max_items = Some(2);
+    let mut readers = [MockReader::new(vec![
+        Step::Data(&items[0..5]),
+        Step::Data(&items[5..15]),
+        Step::Data(&items[15..]),
+    ])];
+    let (reason, stats, output) = run(&mut readers, &options);
+    assert!(matches!(reason, drain::StopReason::LimitReached));
+    assert_eq!(output, &items[0..22]);
+    assert_eq!(stats.items, 2);
+}
+
+// With item_align, each frame is whole items whatever the gets were and
+// a last partial item is never written:
+
+#[test]
+fn item_align_frames_whole_items() {
+    let mut items = Vec::<u8>::new();
+    for (size, fill) in &[
+        (12_u32, b'a'),
+        (10_u32, b'b'),
+        (9_u32, b'c'),
+        (16_u32, b'd'),
+    ] {
+        items.extend_from_slice(&size.to_ne_bytes());
+        items.extend_from_slice(&1_u32.to_ne_bytes());
+        items.resize(items.len() + *size as usize - 8, *fill);
+    }
+    let items: &'static [u8] = Box::leak(items.into_boxed_slice());
+
+    let mut options = drain::Options::new();
+    options.item_align = true;
+    options.frame = true;
+    let mut readers = [MockReader::new(vec![
+        Step::Data(&items[0..5]),
+        Step::Data(&items[5..15]),
+        Step::Data(&items[15..40]),
+    ])];
+    let (_, stats, output) = run(&mut readers, &options);
+    let mut expected = framing::encode_header(0, 12).to_vec();
+    expected.extend_from_slice(&items[0..12]);
+    expected.extend_from_slice(&framing::encode_header(0, 19));
+    expected.extend_from_slice(&items[12..31]);
+    assert_eq!(output, expected);
+    assert_eq!(stats.bytes, 40);
+}
+
+// An item bigger than the buffer isn't held back in full, it's written
+// as it comes and aligning carries on after it:
+
+#[test]
+fn item_align_passes_oversized_items() {
+    let mut items = Vec::<u8>::new();
+    for (size, fill) in &[(40_u32, b'x'), (12_u32, b'a'), (10_u32, b'b')] {
+        items.extend_from_slice(&size.to_ne_bytes());
+        items.extend_from_slice(&1_u32.to_ne_bytes());
+        items.resize(items.len() + *size as usize - 8, *fill);
+    }
+    let items: &'static [u8] = Box::leak(items.into_boxed_slice());
+
+    let mut options = drain::Options::new();
+    options.item_align = true;
+    options.frame = true;
+    options.buffer_size = 16;
+    let mut readers = [MockReader::new(vec![Step::Data(items)])];
+    let (_, stats, output) = run(&mut readers, &options);
+    let mut expected = framing::encode_header(0, 32).to_vec();
+    expected.extend_from_slice(&items[0..32]);
+    expected.extend_from_slice(&framing::encode_header(0, 8));
+    expected.extend_from_slice(&items[32..40]);
+    expected.extend_from_slice(&framing::encode_header(0, 22));
+    expected.extend_from_slice(&items[40..62]);
+    assert_eq!(output, expected);
+    assert_eq!(stats.bytes, 62);
+}
+
+// With a byte limit, item_align only writes the whole items within it;
+// the item the limit cuts is read (and counted) but not written:
+
+#[test]
+fn item_align_byte_limit_is_whole_items() {
+    let mut items = Vec::<u8>::new();
+    for fill in b"abc" {
+        items.extend_from_slice(&12_u32.to_ne_bytes());
+        items.extend_from_slice(&1_u32.to_ne_bytes());
+        items.resize(items.len() + 4, *fill);
+    }
+    let items: &'static [u8] = Box::leak(items.into_boxed_slice());
+
+    let mut options = drain::Options::new();
+    options.item_align = true;
+    options.buffer_size = 16;
+    options.max_bytes = Some(30);
+    let mut readers = [MockReader::new(vec![Step::Data(items)])];
+    let (reason, stats, output) = run(&mut readers, &options);
+    assert!(matches!(reason, drain::StopReason::LimitReached));
+    assert_eq!(output, &items[0..24]);
+    assert_eq!(stats.bytes, 30);
+}
+
+// The same data through the output thread's queue must come out the same:
+
+#[test]
+fn queued_output_matches() {
+    let steps = || {
+        vec![
+            MockReader::new(vec![Step::Data(b"a1"), Step::Timeout, Step::Data(b"a2")]),
+            MockReader::new(vec![Step::Data(b"b1"), Step::Data(b"b2")]),
+        ]
+    };
+    let mut options = drain::Options::new();
+    options.checksum = true;
+    let (_, direct_stats, direct) = run(&mut steps(), &options);
+
+    options.queue_depth = Some(1);
+    let mut output = Vec::<u8>::new();
+    let (reason, stats) =
+        drain::drain_queued(&mut steps(), &mut output, &options, &AtomicBool::new(false));
+    assert!(matches!(reason, drain::StopReason::EndOfData));
+    assert_eq!(output, direct);
+    assert_eq!(stats.bytes, direct_stats.bytes);
+    assert_eq!(
+        stats.checksum.unwrap().crc32(),
+        direct_stats.checksum.unwrap().crc32()
+    );
+}
+
+// A reader that closes its end stops a queued drain too:
+
+struct ClosedPipe;
+impl io::Write for ClosedPipe {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn queued_output_closed() {
+    let data: Vec<Step> = (0..100).map(|_| Step::Data(b"data")).collect();
+    let mut readers = [MockReader::new(data)];
+    let mut options = drain::Options::new();
+    options.queue_depth = Some(2);
+    let (reason, _) =
+        drain::drain_queued(&mut readers, ClosedPipe, &options, &AtomicBool::new(false));
+    assert!(matches!(reason, drain::StopReason::OutputClosed));
+    assert!(readers[0].gets < 100);
+}
+
+#[test]
+fn histogram_counts_gets() {
+    let mut options = drain::Options::new();
+    options.latency_histogram = true;
+    let mut readers = [MockReader::new(vec![
+        Step::Data(b"x"),
+        Step::Timeout,
+        Step::Data(b"0123"),
+        Step::Data(b"abcde"),
+    ])];
+    let (_, stats, _) = run(&mut readers, &options);
+    let histogram = stats.histogram.unwrap();
+    let gets = |buckets: &[drain::Bucket]| buckets.iter().map(|b| b.gets).sum::<u64>();
+    assert_eq!(gets(&histogram.sizes), 3);
+    assert_eq!(gets(&histogram.intervals), 2); // The first get has none.
+    assert_eq!(histogram.sizes[0], drain::Bucket { gets: 1, bytes: 1 });
+    assert_eq!(histogram.sizes[2], drain::Bucket { gets: 2, bytes: 9 });
+}
+
+#[test]
+fn first_data_timeout() {
+    let mut options = drain::Options::new();
+    options.first_data_timeout = Some(Duration::from_millis(50));
+    options.timeout = Duration::from_millis(2);
+
+    let timeouts: Vec<Step> = (0..100).map(|_| Step::Timeout).collect();
+    let mut readers = [SlowMockReader(MockReader::new(timeouts))];
+    let mut output = Vec::<u8>::new();
+    let (reason, stats) =
+        drain::drain(&mut readers, &mut output, &options, &AtomicBool::new(false));
+    assert!(matches!(reason, drain::StopReason::NoData));
+    assert!(output.is_empty());
+    assert!(stats.elapsed() >= Duration::from_millis(50));
+
+    // Once there's been data it doesn't apply:
+
+    let mut steps = vec![Step::Data(b"first")];
+    steps.extend((0..50).map(|_| Step::Timeout));
+    let mut readers = [SlowMockReader(MockReader::new(steps))];
+    let mut output = Vec::<u8>::new();
+    let (reason, _) = drain::drain(&mut readers, &mut output, &options, &AtomicBool::new(false));
+    assert!(matches!(reason, drain::StopReason::EndOfData));
+    assert_eq!(output, b"first");
+}
+
+// A MockReader whose timeouts take the time they say they do:
+
+struct SlowMockReader(MockReader);
+impl RingReader for SlowMockReader {
+    fn timed_get(&mut self, data: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let result = self.0.timed_get(data, timeout);
+        if let Err(Error::ConsumerError(consumer::Error::Timeout)) = &result {
+            std::thread::sleep(timeout);
+        }
+        result
+    }
+}
+
+// However many gets go through the queue, buffers are recycled so only a
+// few are ever allocated:
+
+#[test]
+fn queued_buffers_are_reused() {
+    let steps = || -> Vec<Step> { (0..1000).map(|_| Step::Data(b"0123456789")).collect() };
+    let mut options = drain::Options::new();
+    options.queue_depth = Some(2);
+    let mut output = Vec::<u8>::new();
+    let (_, stats) = drain::drain_queued(
+        &mut [MockReader::new(steps())],
+        &mut output,
+        &options,
+        &AtomicBool::new(false),
+    );
+    assert_eq!(output.len(), 10_000);
+    assert!(
+        stats.buffer_allocations <= 4,
+        "{}",
+        stats.buffer_allocations
+    );
+
+    options.buffer_pool = Some(0);
+    let (_, stats) = drain::drain_queued(
+        &mut [MockReader::new(steps())],
+        io::sink(),
+        &options,
+        &AtomicBool::new(false),
+    );
+    assert_eq!(stats.buffer_allocations, 1001);
+}
+
+#[test]
+fn buffer_count_cycles_that_many_buffers() {
+    // --buffer-count N is a queue of N-1 with a pool of N:
+
+    for count in 2..5 {
+        let steps: Vec<Step> = (0..100).map(|_| Step::Data(b"0123456789")).collect();
+        let mut options = drain::Options::new();
+        options.queue_depth = Some(count - 1);
+        options.buffer_pool = Some(count);
+        let mut output = Vec::<u8>::new();
+        let (_, stats) = drain::drain_queued(
+            &mut [MockReader::new(steps)],
+            &mut output,
+            &options,
+            &AtomicBool::new(false),
+        );
+        assert_eq!(output.len(), 1000);
+        assert!(
+            stats.buffer_allocations <= count as u64,
+            "{}",
+            stats.buffer_allocations
+        );
+    }
+}
@@ -0,0 +1,76 @@
+//!
+//! Tests that ring2stdout falls back to RINGTOSTDOUT_DIR and
+//! RINGTOSTDOUT_RING when --directory and --ring aren't given, and that
+//! the command line takes precedence.
+//!
+
+mod common;
+
+use common::*;
+use std::fs;
+use std::process::{Command, Output};
+
+// Run ring2stdout with args and the environment variables set (or
+// removed if None):
+
+fn run(args: &[&str], dir: Option<&str>, ring: Option<&str>) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_ring2stdout"));
+    command.args(args);
+    for (name, value) in &[("RINGTOSTDOUT_DIR", dir), ("RINGTOSTDOUT_RING", ring)] {
+        match value {
+            Some(value) => command.env(name, value),
+            None => command.env_remove(name),
+        };
+    }
+    command.output().unwrap()
+}
+
+#[test]
+fn directory_from_environment() {
+    let ring = make_ring("env_directory");
+    let directory = ring.parent().unwrap().to_str().unwrap();
+    let empty = std::env::temp_dir().join("ringtostdout_env_empty");
+    fs::create_dir_all(&empty).unwrap();
+
+    let output = run(&["--list"], Some(directory), None);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("env_directory"));
+
+    // --directory wins over the environment:
+
+    let output = run(
+        &["--list", "--directory", empty.to_str().unwrap()],
+        Some(directory),
+        None,
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("env_directory"));
+    fs::remove_file(ring).unwrap();
+}
+
+// With nothing listening on the port manager port, attaching fails (2)
+// once there's a ring name; without one it's a usage error (1).
+
+#[test]
+fn ring_from_environment() {
+    let args = ["--directory", "/tmp", "--port", "1", "--dry-run"];
+
+    let output = run(&args, None, Some("env_ring"));
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("env_ring"));
+
+    let output = run(&args, None, None);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("RINGTOSTDOUT_RING"));
+
+    // --ring wins, and the environment's ring is checked like --ring:
+
+    let mut with_ring = args.to_vec();
+    with_ring.extend_from_slice(&["--ring", "cli_ring"]);
+    let output = run(&with_ring, None, Some("../escape"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("cli_ring") && !stderr.contains("escape"));
+
+    let output = run(&args, None, Some("../escape"));
+    assert_eq!(output.status.code(), Some(1));
+}
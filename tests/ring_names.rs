@@ -0,0 +1,67 @@
+//!
+//! Tests that ring names can't be used to escape the ring buffer
+//! directory, both in the library check and on the ring2stdout
+//! command line.
+//!
+
+use nscldaq_ringtostdout::ringmaster_client::is_simple_ring_name;
+use std::process::Command;
+
+const ESCAPES: &[&str] = &[
+    "../../etc/passwd",
+    "..",
+    ".",
+    "/etc/passwd",
+    "sub/ring",
+    "ring/",
+    "./ring",
+    "",
+];
+
+#[test]
+fn simple_names_accepted() {
+    for name in &["fox", "ring.1", "my-ring_2", "..hidden", "a..b"] {
+        assert!(is_simple_ring_name(name), "{} should be accepted", name);
+    }
+}
+
+#[test]
+fn escapes_rejected() {
+    for name in ESCAPES {
+        assert!(!is_simple_ring_name(name), "{} should be rejected", name);
+    }
+}
+
+// The program must refuse the name before trying to attach.  The port
+// manager port is one nothing should be listening on in case it doesn't.
+
+#[test]
+fn command_line_rejects_escapes() {
+    for name in ESCAPES {
+        let output = Command::new(env!("CARGO_BIN_EXE_ring2stdout"))
+            .args([
+                "--directory",
+                "/tmp",
+                "--port",
+                "1",
+                "--dry-run",
+                "--ring",
+                name,
+            ])
+            .output()
+            .unwrap();
+        assert_eq!(
+            output.status.code(),
+            Some(1),
+            "{} should be a usage error",
+            name
+        );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("must be a plain file name"),
+            "{}: unexpected stderr {}",
+            name,
+            stderr
+        );
+    }
+}
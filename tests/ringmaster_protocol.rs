@@ -0,0 +1,595 @@
+//!
+//! Tests of the ringmaster_client protocol handling against mock
+//! port manager and ringmaster servers listening on ephemeral ports.
+//!
+
+mod common;
+
+use common::*;
+use nscldaq_ringtostdout::ringmaster_client;
+use std::fs;
+use std::process;
+use std::time::Duration;
+
+#[test]
+fn consumer_connect_request() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ring = make_ring("consumer_connect");
+    let ringmaster = MockRingMaster::start(Reply::Line("OK\n"));
+    use_mocks(&ringmaster);
+
+    let result = ringmaster_client::attach_consumer(ring.to_str().unwrap());
+    let name = ring.file_name().unwrap().to_str().unwrap();
+    assert_eq!(
+        ringmaster.request(),
+        format!(
+            "CONNECT {{{}}} consumer.0 {} RUST Client\n",
+            name,
+            process::id()
+        )
+    );
+    let client = result.unwrap();
+    match client.ringmaster_stream() {
+        ringmaster_client::RingMasterStream::Tcp(stream) => {
+            assert_eq!(stream.peer_addr().unwrap().port(), ringmaster.port)
+        }
+        stream => panic!("not connected over TCP: {:?}", stream),
+    }
+    assert_eq!(client.client.slot(), Some(0));
+    assert_eq!(client.producer_attached(), Some(false));
+    drop(client);
+    fs::remove_file(ring).unwrap();
+}
+
+#[test]
+fn producer_connect_request() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ring = make_ring("producer_connect");
+    let ringmaster = MockRingMaster::start(Reply::Line("OK\n"));
+    use_mocks(&ringmaster);
+
+    let result = ringmaster_client::attach_producer(ring.to_str().unwrap());
+    let name = ring.file_name().unwrap().to_str().unwrap();
+    assert_eq!(
+        ringmaster.request(),
+        format!(
+            "CONNECT {{{}}} producer {} RUST Client\n",
+            name,
+            process::id()
+        )
+    );
+    let client = result.unwrap();
+    assert_eq!(client.producer_attached(), Some(true));
+    drop(client);
+    fs::remove_file(ring).unwrap();
+}
+
+#[test]
+fn error_reply() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ring = make_ring("error_reply");
+    let ringmaster = MockRingMaster::start(Reply::Line("ERROR Ring error_reply does not exist\n"));
+    use_mocks(&ringmaster);
+
+    match ringmaster_client::attach_consumer(ring.to_str().unwrap()) {
+        Err(ringmaster_client::Error::RingMasterNoRing(line)) => {
+            assert_eq!(line, "ERROR Ring error_reply does not exist")
+        }
+        Err(e) => panic!("Wrong error: {}", e),
+        Ok(_) => panic!("Attach should have failed"),
+    }
+    ringmaster.request();
+    fs::remove_file(ring).unwrap();
+}
+
+// The ringmaster's replies we act on are recognized whole; anything
+// else, however like them, keeps the line as it came:
+
+#[test]
+fn error_reply_kinds() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ring = make_ring("error_reply_kinds");
+    for (reply, expected) in &[
+        ("ERROR Connect must be from localhost\n", "denied"),
+        ("ERROR Ring poop does not exist\n", "no ring"),
+        ("ERROR Ring with spaces does not exist\n", "no ring"),
+        ("ERROR something else\n", "unknown"),
+        ("ERROR no such ring\n", "unknown"),
+        ("ERROR Ring  does not exist\n", "unknown"),
+        ("ERROR data file not found\n", "unknown"),
+        ("ERROR permission denied\n", "unknown"),
+        (
+            "ERROR Connect must be from localhost or a trusted host\n",
+            "unknown",
+        ),
+        ("ERROR consumer slot already in use\n", "unknown"),
+        ("ERRORRing poop does not exist\n", "unknown"),
+        ("WHAT?\n", "unknown"),
+    ] {
+        let ringmaster = MockRingMaster::start(Reply::Line(reply));
+        use_mocks(&ringmaster);
+        let kind = match ringmaster_client::attach_consumer(ring.to_str().unwrap()) {
+            Err(ringmaster_client::Error::RingMasterDenied(_)) => "denied",
+            Err(ringmaster_client::Error::RingMasterNoRing(_)) => "no ring",
+            Err(ringmaster_client::Error::RingMasterFail(line)) => {
+                assert_eq!(line, reply.trim());
+                "unknown"
+            }
+            Err(e) => panic!("Wrong error: {}", e),
+            Ok(_) => panic!("Attach should have failed"),
+        };
+        assert_eq!(&kind, expected, "{}", reply);
+        ringmaster.request();
+    }
+    fs::remove_file(ring).unwrap();
+}
+
+#[test]
+fn refused_connect_frees_slot() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ring = make_ring("refused_connect");
+    let ring_file = ring.to_str().unwrap();
+
+    for _ in 0..2 {
+        let ringmaster = MockRingMaster::start(Reply::Line("ERROR go away\n"));
+        use_mocks(&ringmaster);
+        assert!(matches!(
+            ringmaster_client::attach_consumer(ring_file),
+            Err(ringmaster_client::Error::RingMasterFail(_))
+        ));
+        ringmaster.request();
+        assert_eq!(
+            ringmaster_client::ring_info(ring_file).unwrap().consumers,
+            0
+        );
+    }
+
+    let ringmaster = MockRingMaster::start(Reply::Line("ERROR go away\n"));
+    use_mocks(&ringmaster);
+    assert!(ringmaster_client::attach_producer(ring_file).is_err());
+    ringmaster.request();
+    assert_eq!(
+        ringmaster_client::ring_info(ring_file).unwrap().producer,
+        None
+    );
+
+    // So a producer the ringmaster does accept can have the ring:
+
+    let ringmaster = MockRingMaster::start(Reply::Line("OK\n"));
+    use_mocks(&ringmaster);
+    let producer = ringmaster_client::attach_producer(ring_file).unwrap();
+    ringmaster.request();
+    assert_eq!(
+        ringmaster_client::ring_info(ring_file).unwrap().producer,
+        Some(std::process::id())
+    );
+    drop(producer);
+    fs::remove_file(ring).unwrap();
+}
+
+#[test]
+fn no_port_manager() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ring = make_ring("no_port_manager");
+    let ringmaster = MockRingMaster::start(Reply::Line("OK\n"));
+    use_mocks(&ringmaster);
+
+    // Nothing listens on a port we just had:
+
+    let unused = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    ringmaster_client::set_portman_port(unused);
+    let result = ringmaster_client::attach_consumer(ring.to_str().unwrap());
+    assert!(matches!(
+        result,
+        Err(ringmaster_client::Error::NoPortManager(_))
+    ));
+    fs::remove_file(ring).unwrap();
+}
+
+// A port manager that isn't up yet is waited for with retries, but one
+// that can't be reached at all still fails in the end:
+
+#[test]
+fn port_manager_retries() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ring = make_ring("portman_retries");
+    let ringmaster = MockRingMaster::start(Reply::Line("OK\n"));
+    use_mocks(&ringmaster);
+    let unused = || {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    };
+    ringmaster_client::set_portman_retries(5);
+
+    let late = unused();
+    start_port_manager_later(late, ringmaster.port, Duration::from_millis(250));
+    ringmaster_client::set_portman_port(late);
+    let client = ringmaster_client::attach_consumer(ring.to_str().unwrap()).unwrap();
+    ringmaster.request();
+    drop(client);
+
+    ringmaster_client::set_portman_retries(2);
+    ringmaster_client::set_portman_port(unused());
+    let start = std::time::Instant::now();
+    assert!(matches!(
+        ringmaster_client::attach_consumer(ring.to_str().unwrap()),
+        Err(ringmaster_client::Error::NoPortManager(_))
+    ));
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    ringmaster_client::set_portman_retries(0);
+    fs::remove_file(ring).unwrap();
+}
+
+#[test]
+fn no_reply_times_out() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ring = make_ring("no_reply");
+    let ringmaster = MockRingMaster::start(Reply::Nothing);
+    use_mocks(&ringmaster);
+    ringmaster_client::set_ringmaster_timeout(Duration::from_millis(200));
+
+    let result = ringmaster_client::attach_consumer(ring.to_str().unwrap());
+    assert!(matches!(
+        result,
+        Err(ringmaster_client::Error::RingMasterTimeout)
+    ));
+    ringmaster.request();
+    fs::remove_file(ring).unwrap();
+}
+
+#[test]
+fn closed_without_reply() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ring = make_ring("closed");
+    let ringmaster = MockRingMaster::start(Reply::Close);
+    use_mocks(&ringmaster);
+
+    let result = ringmaster_client::attach_consumer(ring.to_str().unwrap());
+    assert!(matches!(
+        result,
+        Err(ringmaster_client::Error::RingMasterIo(_))
+    ));
+    fs::remove_file(ring).unwrap();
+}
+
+// Consumers and producers share the request/reply handling so each way
+// the ringmaster can fail us must come out the same for both:
+
+#[test]
+fn shared_attach_error_paths() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ring = make_ring("shared_errors");
+    let ring_file = ring.to_str().unwrap();
+    let attaches: [fn(&str) -> ringmaster_client::RingClientResult; 2] = [
+        |ring| ringmaster_client::attach_consumer(ring),
+        |ring| ringmaster_client::attach_producer(ring),
+    ];
+    for attach in &attaches {
+        for (reply, expected) in &[
+            (
+                Reply::Line("ERROR Ring shared_errors does not exist\n"),
+                "no ring",
+            ),
+            (Reply::Line("ERROR go away\n"), "fail"),
+            (Reply::Nothing, "timeout"),
+            (Reply::Close, "io"),
+        ] {
+            let ringmaster = MockRingMaster::start(reply.clone());
+            use_mocks(&ringmaster);
+            ringmaster_client::set_ringmaster_timeout(Duration::from_millis(200));
+            let kind = match attach(ring_file) {
+                Err(ringmaster_client::Error::RingMasterNoRing(_)) => "no ring",
+                Err(ringmaster_client::Error::RingMasterFail(_)) => "fail",
+                Err(ringmaster_client::Error::RingMasterTimeout) => "timeout",
+                Err(ringmaster_client::Error::RingMasterIo(_)) => "io",
+                Err(e) => panic!("Wrong error: {}", e),
+                Ok(_) => panic!("Attach should have failed"),
+            };
+            assert_eq!(&kind, expected);
+            ringmaster.request();
+        }
+    }
+    let info = ringmaster_client::ring_info(ring_file).unwrap();
+    assert_eq!(info.consumers, 0);
+    assert_eq!(info.producer, None);
+    fs::remove_file(ring).unwrap();
+}
+
+#[test]
+fn remote_request() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ringmaster =
+        MockRingMaster::start(Reply::LineThenData("OK BINARY FOLLOWS\n", b"ring data"));
+    use_mocks(&ringmaster);
+
+    let mut client = ringmaster_client::attach_remote_consumer("127.0.0.1", "fox").unwrap();
+    assert_eq!(ringmaster.request(), "REMOTE {fox}\n");
+
+    // The data after the reply line must not have been swallowed:
+
+    let mut data = Vec::<u8>::new();
+    let mut buffer = [0_u8; 100];
+    loop {
+        match client.client.timed_get(&mut buffer, Duration::from_secs(1)) {
+            Ok(0) => break,
+            Ok(n) => data.extend_from_slice(&buffer[0..n]),
+            Err(e) => panic!("Read failed: {}", e),
+        }
+    }
+    assert_eq!(data, b"ring data");
+}
+
+#[test]
+fn directory_is_not_a_ring() {
+    let mut dir = std::env::temp_dir();
+    dir.push(format!("ring2stdout_test_directory_{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    match ringmaster_client::attach_consumer(dir.to_str().unwrap()) {
+        Err(ringmaster_client::Error::MapError(message)) => {
+            assert!(message.contains("is a directory"), "{}", message)
+        }
+        Err(e) => panic!("Wrong error: {}", e),
+        Ok(_) => panic!("Attach should have failed"),
+    }
+    fs::remove_dir(dir).unwrap();
+}
+
+#[test]
+fn requested_slot() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ring = make_ring("requested_slot");
+    let ring_file = ring.to_str().unwrap();
+    ringmaster_client::set_consumer_slot(Some(2));
+
+    let ringmaster = MockRingMaster::start(Reply::Line("OK\n"));
+    use_mocks(&ringmaster);
+    let client = ringmaster_client::attach_consumer(ring_file).unwrap();
+    let request = ringmaster.request();
+    assert!(request.contains(" consumer.2 "), "{}", request);
+    assert_eq!(client.client.slot(), Some(2));
+    assert_eq!(
+        ringmaster_client::ring_info(ring_file).unwrap().consumers,
+        1
+    );
+
+    // It's ours now, so asking again fails:
+
+    assert!(matches!(
+        ringmaster_client::attach_consumer(ring_file),
+        Err(ringmaster_client::Error::SlotTaken(2, _))
+    ));
+    drop(client);
+    assert_eq!(
+        ringmaster_client::ring_info(ring_file).unwrap().consumers,
+        0
+    );
+
+    ringmaster_client::set_consumer_slot(Some(4));
+    assert!(matches!(
+        ringmaster_client::attach_consumer(ring_file),
+        Err(ringmaster_client::Error::BadSlot(4, 4))
+    ));
+    ringmaster_client::set_consumer_slot(None);
+    fs::remove_file(ring).unwrap();
+}
+
+// Slots are only taken in the ring itself; a refusal mentioning the slot
+// we asked for keeps its own error:
+
+#[test]
+fn slot_refusal_keeps_its_own_error() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ring = make_ring("slot_refusal");
+    let ring_file = ring.to_str().unwrap();
+    ringmaster_client::set_consumer_slot(Some(1));
+
+    let ringmaster = MockRingMaster::start(Reply::Line("ERROR consumer.1 is in use\n"));
+    use_mocks(&ringmaster);
+    let result = ringmaster_client::attach_consumer(ring_file);
+    ringmaster_client::set_consumer_slot(None);
+    assert!(matches!(
+        result,
+        Err(ringmaster_client::Error::RingMasterFail(_))
+    ));
+    ringmaster.request();
+    assert_eq!(
+        ringmaster_client::ring_info(ring_file).unwrap().consumers,
+        0
+    );
+    fs::remove_file(ring).unwrap();
+}
+
+#[test]
+fn list_request() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ringmaster = MockRingMaster::start(Reply::Line(
+        "OK\n{zeta {8192 8192 4 -1 0 0 {}}} {alpha {4096 100 4 1234 0 0 {{1234 0} {55 10}}}} odd\n",
+    ));
+    use_mocks(&ringmaster);
+
+    let rings = ringmaster_client::list_ringmaster_rings().unwrap();
+    assert_eq!(ringmaster.request(), "LIST\n");
+    let listed = |name: &str, data_bytes, producer, consumers| ringmaster_client::ListedRing {
+        name: String::from(name),
+        data_bytes,
+        producer,
+        consumers,
+    };
+    assert_eq!(
+        rings,
+        vec![
+            listed("alpha", Some(4096), Some(1234), 2),
+            listed("odd", None, None, 0),
+            listed("zeta", Some(8192), None, 0),
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_path_is_an_error() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ringmaster = MockRingMaster::start(Reply::Line("OK\n"));
+    use_mocks(&ringmaster);
+    let path = std::env::temp_dir().join(OsStr::from_bytes(b"ring\xff"));
+    assert!(matches!(
+        ringmaster_client::attach_consumer(&path),
+        Err(ringmaster_client::Error::MapError(_))
+    ));
+    assert!(ringmaster_client::ring_info(&path).is_err());
+}
+
+#[test]
+fn wrong_ring_format_is_an_error() {
+    use std::io::{Seek, SeekFrom, Write};
+
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ringmaster = MockRingMaster::start(Reply::Line("OK\n"));
+    use_mocks(&ringmaster);
+    let ring = make_ring("wrong_ring_format");
+
+    // Another format, and a magic that isn't even text (which the map
+    // itself would panic on):
+
+    for magic in [&b"OtherFmt"[..], &b"NSCL\xffing"[..]] {
+        let mut file = fs::OpenOptions::new().write(true).open(&ring).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(magic).unwrap();
+        drop(file);
+        match ringmaster_client::attach_consumer(&ring) {
+            Err(ringmaster_client::Error::MapError(message)) => {
+                assert!(
+                    message.contains("expected the magic string \"NSCLRing\""),
+                    "{}",
+                    message
+                );
+                assert!(
+                    message.contains("found \"O") || message.contains("found \"NSCL"),
+                    "{}",
+                    message
+                );
+            }
+            _ => panic!("attached to a ring with a bad magic string"),
+        }
+        assert!(ringmaster_client::ring_info(&ring).is_err());
+    }
+    fs::remove_file(ring).unwrap();
+}
+
+#[test]
+fn find_ringmaster_port() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    ringmaster_client::set_ringmaster_index(None);
+    ringmaster_client::set_portman_port(start_port_manager(4321));
+    assert_eq!(ringmaster_client::find_ringmaster_port().unwrap(), 4321);
+
+    // The mock port manager also lists an Other service on port 1234:
+
+    ringmaster_client::set_ringmaster_service("Other");
+    assert_eq!(ringmaster_client::find_ringmaster_port().unwrap(), 1234);
+    ringmaster_client::set_ringmaster_service("Missing");
+    assert!(matches!(
+        ringmaster_client::find_ringmaster_port(),
+        Err(ringmaster_client::Error::NoRingMaster)
+    ));
+    ringmaster_client::set_ringmaster_service(ringmaster_client::RINGMASTER_SERVICE);
+}
+
+#[test]
+fn unix_socket_ringmaster() {
+    use ringmaster_client::RingMasterSocket;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixListener};
+
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let ring = make_ring("unix_socket_ringmaster");
+    let ring_file = ring.to_str().unwrap();
+    let name = ring.file_name().unwrap().to_str().unwrap();
+
+    // No port manager is asked:
+
+    ringmaster_client::set_portman_port(1);
+    let path = std::env::temp_dir().join(format!("ringmaster_{}.sock", process::id()));
+    let _ = fs::remove_file(&path);
+    let abstract_name = format!("ringmaster_{}", process::id());
+    let listeners = [
+        (
+            UnixListener::bind(&path).unwrap(),
+            RingMasterSocket::Path(path.clone()),
+        ),
+        (
+            UnixListener::bind_addr(&SocketAddr::from_abstract_name(&abstract_name).unwrap())
+                .unwrap(),
+            RingMasterSocket::Abstract(abstract_name.clone()),
+        ),
+    ];
+    for (listener, socket) in listeners {
+        ringmaster_client::set_ringmaster_socket(Some(socket));
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+            stream.write_all(b"OK\n").unwrap();
+            request
+        });
+        let client = ringmaster_client::attach_consumer(ring_file).unwrap();
+        assert_eq!(
+            server.join().unwrap(),
+            format!(
+                "CONNECT {{{}}} consumer.0 {} RUST Client\n",
+                name,
+                process::id()
+            )
+        );
+        assert!(matches!(
+            client.ringmaster_stream(),
+            ringmaster_client::RingMasterStream::Unix(_)
+        ));
+        // The server side has closed:
+
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(client.ringmaster_connected().is_err());
+    }
+
+    // Nobody listening is no ringmaster:
+
+    fs::remove_file(&path).unwrap();
+    ringmaster_client::set_ringmaster_socket(Some(RingMasterSocket::Path(path)));
+    assert!(matches!(
+        ringmaster_client::attach_consumer(ring_file),
+        Err(ringmaster_client::Error::NoRingMaster)
+    ));
+    ringmaster_client::set_ringmaster_socket(None);
+    fs::remove_file(ring).unwrap();
+}
+
+#[test]
+fn silent_remote_port_manager_times_out() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+
+    // A port manager that accepts the connection but never answers:
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    ringmaster_client::set_portman_port(listener.local_addr().unwrap().port());
+    ringmaster_client::set_ringmaster_timeout(Duration::from_millis(200));
+    let start = std::time::Instant::now();
+    assert!(matches!(
+        ringmaster_client::attach_remote_consumer("127.0.0.1", "ring"),
+        Err(ringmaster_client::Error::RingMasterTimeout)
+    ));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    ringmaster_client::set_ringmaster_timeout(Duration::from_secs(5));
+    drop(listener);
+}
@@ -0,0 +1,81 @@
+//!
+//! Tests that ring2stdout can be stopped by a signal while it waits
+//! before forwarding anything: for a --unix-listen reader to connect and
+//! between --retry attempts to attach.
+//!
+
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn start(args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_ring2stdout"))
+        .args(args)
+        .env_remove("RINGTOSTDOUT_DIR")
+        .env_remove("RINGTOSTDOUT_RING")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+fn signal(child: &Child, name: &str) {
+    let status = Command::new("kill")
+        .args(["-s", name, &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+// Wait up to 5 seconds for child to exit, killing it if it doesn't:
+
+fn exits(child: &mut Child) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if child.try_wait().unwrap().is_some() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+    false
+}
+
+#[test]
+fn sigterm_while_listening() {
+    let socket = std::env::temp_dir().join(format!("signals_{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket);
+    let mut child = start(&[
+        "--directory",
+        "/tmp",
+        "--ring",
+        "ring",
+        "--unix-socket",
+        socket.to_str().unwrap(),
+        "--unix-listen",
+    ]);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !socket.exists() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert!(socket.exists());
+    signal(&child, "TERM");
+    assert!(exits(&mut child), "still waiting for a connection");
+    assert!(!socket.exists(), "the socket was left behind");
+}
+
+#[test]
+fn sigint_while_retrying() {
+    let mut child = start(&[
+        "--directory",
+        "/tmp",
+        "--ring",
+        "no_such_ring_for_signals",
+        "--retry",
+        "100",
+    ]);
+    thread::sleep(Duration::from_millis(500));
+    signal(&child, "INT");
+    assert!(exits(&mut child), "still retrying");
+}
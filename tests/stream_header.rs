@@ -0,0 +1,27 @@
+//!
+//! Tests of the --with-header stream header encoding.
+//!
+
+use nscldaq_ringtostdout::framing;
+use std::io;
+
+#[test]
+fn round_trip() {
+    let header = framing::StreamHeader::new("fox,dog");
+    let mut bytes = Vec::<u8>::new();
+    framing::write_stream_header(&mut bytes, &header).unwrap();
+    assert_eq!(&bytes[0..8], b"RING2OUT");
+    assert_eq!(bytes.len(), 8 + 2 + 2 + 7 + 8);
+    bytes.extend_from_slice(b"data");
+
+    let mut input = bytes.as_slice();
+    assert_eq!(framing::read_stream_header(&mut input).unwrap(), header);
+    assert_eq!(input, b"data"); // Only the header was read.
+}
+
+#[test]
+fn not_a_header() {
+    let mut input: &[u8] = b"plain ring data, no header at all";
+    let e = framing::read_stream_header(&mut input).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+}